@@ -1,61 +1,158 @@
 use cluster::{Cluster, Error, Node, Role};
-use std::collections::HashMap;
+use create::{colocated_warning, place_replicas, DOMAIN_HINT};
+use plan::{Plan, Step};
+use topology::split_addr;
+use zones::Zones;
+
+#[test]
+fn test_spread_slaves() {
+    let pair = |slave: &str, master: &str| (slave.to_string(), master.to_string());
+    let zones = Zones::parse("10.0.0.1 a\n10.0.0.2 a\n10.0.0.3 b\n10.0.0.4 b").unwrap();
+    // safe pairs are kept as given.
+    let pairs = vec![pair("10.0.0.3:7000", "10.0.0.1:7000")];
+    assert_eq!(spread_slaves(pairs.clone(), &zones, true), pairs);
+    let pairs = vec![
+        pair("10.0.0.2:7000", "10.0.0.1:7000"),
+        pair("10.0.0.4:7000", "10.0.0.3:7000"),
+    ];
+    // the given pairs win unless asked to spread them.
+    assert_eq!(spread_slaves(pairs.clone(), &zones, false), pairs);
+    assert_eq!(
+        spread_slaves(pairs, &zones, true),
+        vec![
+            pair("10.0.0.2:7000", "10.0.0.3:7000"),
+            pair("10.0.0.4:7000", "10.0.0.1:7000"),
+        ]
+    );
+    // nothing better to do in a single domain.
+    let pairs = vec![pair("10.0.0.2:7000", "10.0.0.1:7000")];
+    assert_eq!(spread_slaves(pairs.clone(), &zones, true), pairs);
+}
+
 #[derive(Debug)]
 pub struct Add {
+    #[allow(dead_code)]
     origin: String,
     pub cluster: Cluster,
     node: Node,
-    slave_master: HashMap<String, String>,
+    // slave_master pair the address of each new slave with its master.
+    slave_master: Vec<(String, String)>,
 }
 
 impl Add {
-    pub fn new(origin: String, addrs: Vec<String>) -> Result<Add, Error> {
-        let mut node = Node::new(origin.as_bytes()).unwrap();
+    pub fn new(
+        origin: String,
+        addrs: Vec<String>,
+        zones: &Zones,
+        spread: bool,
+    ) -> Result<Add, Error> {
+        let mut node = Node::new(origin.as_bytes())?;
 
-        node.connect();
+        node.connect()?;
         let mut nodes = Vec::new();
-        let mut sm = HashMap::new();
+        let mut sm = Vec::new();
         for n in addrs.into_iter() {
             let mut ms: Vec<&str> = n.split(',').collect();
             let master_host = ms.pop().unwrap();
-            let mut master = Node::new(master_host.as_bytes()).expect("create new node fail");
-            master.connect();
+            let mut master = Node::new(master_host.as_bytes())?;
+            master.connect()?;
+            let master_addr = master.addr();
             nodes.push(master);
             if ms.len() == 1 {
                 let slave_host = ms.pop().unwrap();
-                let mut node = Node::new(slave_host.as_bytes()).expect("create new node fail");
+                let mut node = Node::new(slave_host.as_bytes())?;
                 node.set_role(Role::Slave);
+                sm.push((node.addr(), master_addr));
                 nodes.push(node);
-                sm.insert(slave_host.to_string(), master_host.to_string());
             }
         }
         Ok(Add {
             origin,
-            slave_master: sm,
+            slave_master: spread_slaves(sm, zones, spread),
             cluster: Cluster::new(nodes),
             node,
         })
     }
-    pub fn add_node(&self) -> Result<(), Error> {
+    // plan let the cluster meet every new node, then set the new slaves once
+    // the cluster is consistent.
+    pub fn plan(&self) -> Plan {
+        let mut plan = Plan::default();
         for node in &self.cluster.nodes {
-            self.node.meet(&*node.ip, &*node.port);
+            plan.push(Step::Meet {
+                node: self.node.addr(),
+                peer: node.addr(),
+            });
         }
-        Ok(())
-    }
-    pub fn set_slave(&mut self) {
-        let mut nodes_info = HashMap::new();
-        for node in &self.cluster.nodes {
-            nodes_info.insert(node.ip.clone() + ":" + &*node.port, node.clone());
+        plan.push(Step::WaitConsistent {
+            nodes: self.cluster.nodes.iter().map(|n| n.addr()).collect(),
+        });
+        for (slave, master) in &self.slave_master {
+            plan.push(Step::Replicate {
+                node: slave.clone(),
+                master: master.clone(),
+            });
         }
-        println!("nodes_info {:?}", nodes_info);
-        println!("s_m info {:?}", self.slave_master);
-        for node in self.cluster.nodes.iter_mut() {
-            if self.slave_master.contains_key(&*node.addr()) {
-                let master = &self.slave_master[&node.addr()];
-                let master_node = nodes_info.get(master);
-                node.slaveof = Some(master_node.unwrap().clone().name);
-                let _: () = node.set_slave();
+        plan
+    }
+}
+
+// spread_slaves keep the slaves with the master they were given and warn about
+// the pairs sharing a failure domain. With spread the slaves of such pairs are
+// placed again over the same masters so every shard has a copy out of the
+// domain of its master.
+fn spread_slaves(
+    pairs: Vec<(String, String)>,
+    zones: &Zones,
+    spread: bool,
+) -> Vec<(String, String)> {
+    let domain = |addr: &str| match split_addr(addr) {
+        Some((host, port)) => zones.domain(&host, &port),
+        None => addr.to_string(),
+    };
+    let slaves: Vec<String> = pairs.iter().map(|(slave, _)| domain(slave)).collect();
+    let masters: Vec<String> = pairs.iter().map(|(_, master)| domain(master)).collect();
+    if slaves
+        .iter()
+        .zip(&masters)
+        .all(|(slave, master)| slave != master)
+    {
+        return pairs;
+    }
+    if !spread {
+        for (idx, (slave, master)) in pairs.iter().enumerate() {
+            if slaves[idx] == masters[idx] {
+                eprintln!(
+                    "{}",
+                    colocated_warning(
+                        slave,
+                        &slaves[idx],
+                        master,
+                        "pass --spread to pair the new slaves again"
+                    )
+                );
             }
         }
+        return pairs;
+    }
+    let placement = place_replicas(
+        &masters.iter().map(|m| m.as_str()).collect::<Vec<_>>(),
+        &slaves.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+    );
+    let mut spread = Vec::new();
+    for (idx, (slave, master)) in pairs.iter().enumerate() {
+        let target = &pairs[placement.masters[idx]].1;
+        if placement.colocated.contains(&idx) {
+            eprintln!(
+                "{}",
+                colocated_warning(slave, &slaves[idx], target, DOMAIN_HINT)
+            );
+        } else if target != master {
+            eprintln!(
+                "note: replica {} will follow {} instead of {} which is in the same {}",
+                slave, target, master, slaves[idx]
+            );
+        }
+        spread.push((slave.clone(), target.clone()));
     }
+    spread
 }
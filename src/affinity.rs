@@ -0,0 +1,343 @@
+use plan::{Plan, Step};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter;
+use std::mem;
+use topology::{split_addr, Topology};
+use zones::Zones;
+
+#[cfg(test)]
+fn replicate(node: &str, master: &str) -> Step {
+    Step::Replicate {
+        node: node.to_string(),
+        master: master.to_string(),
+    }
+}
+
+#[test]
+fn test_swap_replicas() {
+    let text = "\
+aaaa 10.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-8191
+bbbb 10.0.0.2:7000@17000 master - 0 0 2 connected 8192-16383
+cccc 10.0.0.1:7001@17001 slave aaaa 0 0 1 connected
+dddd 10.0.0.2:7001@17001 slave bbbb 0 0 2 connected
+eeee 10.0.0.2:7002@17002 slave,fail bbbb 0 0 2 disconnected
+";
+    let shards = shards(&Topology::parse(text).unwrap());
+    assert_eq!(shards.len(), 2);
+    assert_eq!(shards[1].replicas, vec!["10.0.0.2:7001".to_string()]);
+    let findings = audit(&shards, &Zones::default());
+    assert_eq!(findings.len(), 2);
+    assert_eq!(
+        findings[0].to_string(),
+        "shard 10.0.0.1:7000: 10.0.0.1:7000,10.0.0.1:7001 share host 10.0.0.1"
+    );
+    let repair = repair(&shards, &Zones::default());
+    assert_eq!(
+        repair.plan.steps,
+        vec![
+            replicate("10.0.0.2:7001", "10.0.0.1:7000"),
+            replicate("10.0.0.1:7001", "10.0.0.2:7000"),
+        ]
+    );
+    assert!(repair.unsafe_shards.is_empty());
+
+    // a single host can't be fixed.
+    let text = text.replace("10.0.0.2:70", "10.0.0.1:80");
+    let shards = self::shards(&Topology::parse(&text).unwrap());
+    let repair = self::repair(&shards, &Zones::default());
+    assert!(repair.plan.steps.is_empty());
+    assert_eq!(repair.unsafe_shards.len(), 2);
+}
+
+#[test]
+fn test_crowded_masters() {
+    let text = "\
+aaaa 10.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-5000
+bbbb 10.0.0.1:7001@17001 master - 0 0 2 connected 5001-10000
+cccc 10.0.0.2:7000@17000 master - 0 0 3 connected 10001-16383
+dddd 10.0.0.3:7000@17000 slave aaaa 0 0 1 connected
+eeee 10.0.0.2:7001@17001 slave bbbb 0 0 2 connected
+ffff 10.0.0.3:7001@17001 slave cccc 0 0 3 connected
+";
+    let shards = shards(&Topology::parse(text).unwrap());
+    let findings = audit(&shards, &Zones::default());
+    assert_eq!(
+        findings,
+        vec![Finding::Crowded {
+            domain: "host 10.0.0.1".to_string(),
+            masters: vec!["10.0.0.1:7000".to_string(), "10.0.0.1:7001".to_string()],
+            even: 1,
+        }]
+    );
+    let repair = repair(&shards, &Zones::default());
+    assert_eq!(
+        repair.plan.steps,
+        vec![Step::Failover {
+            node: "10.0.0.3:7000".to_string(),
+        }]
+    );
+    assert!(repair.unsafe_shards.is_empty());
+
+    // zones a and b: one shard can't have a copy in zone b.
+    let zones = Zones::parse("10.0.0.1 a\n10.0.0.2 a\n10.0.0.3 b").unwrap();
+    let findings = audit(&shards, &zones);
+    assert_eq!(findings.len(), 2);
+    assert_eq!(
+        findings[0].to_string(),
+        "shard 10.0.0.1:7001: 10.0.0.1:7001,10.0.0.2:7001 share zone a"
+    );
+    let repair = self::repair(&shards, &zones);
+    assert_eq!(repair.plan.steps.len(), 1);
+    assert_eq!(repair.unsafe_shards, vec!["10.0.0.1:7001".to_string()]);
+}
+
+// Shard is a master owning slots with its replicas, failed nodes are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shard {
+    pub master: String,
+    pub replicas: Vec<String>,
+}
+
+// shards list the shards of topology ordered by master address.
+pub fn shards(topology: &Topology) -> Vec<Shard> {
+    let mut shards: Vec<Shard> = topology
+        .masters()
+        .filter(|m| !m.slots.is_empty() && m.flags.is_available())
+        .map(|m| Shard {
+            master: m.addr.clone(),
+            replicas: topology
+                .nodes
+                .iter()
+                .filter(|n| n.master.as_ref() == Some(&m.id) && n.flags.is_available())
+                .map(|n| n.addr.clone())
+                .collect(),
+        })
+        .collect();
+    shards.sort_by(|a, b| a.master.cmp(&b.master));
+    shards
+}
+
+// Finding is a placement problem of the cluster.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Finding {
+    // Shared is a group of copies of the shard of master on one host or zone.
+    Shared {
+        master: String,
+        domain: String,
+        nodes: Vec<String>,
+    },
+    // Crowded is a domain holding more masters than an even share, losing it
+    // fails over more shards at once.
+    Crowded {
+        domain: String,
+        masters: Vec<String>,
+        even: usize,
+    },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Finding::Shared {
+                master,
+                domain,
+                nodes,
+            } => write!(f, "shard {}: {} share {}", master, nodes.join(","), domain),
+            Finding::Crowded {
+                domain,
+                masters,
+                even,
+            } => write!(
+                f,
+                "{} holds {} masters {}, an even share is {}",
+                domain,
+                masters.len(),
+                masters.join(","),
+                even
+            ),
+        }
+    }
+}
+
+// domain is the failure domain of the node at addr, its zone when labelled
+// or else its host.
+fn domain(zones: &Zones, addr: &str) -> String {
+    match split_addr(addr) {
+        Some((host, port)) => zones.domain(&host, &port),
+        None => addr.to_string(),
+    }
+}
+
+// masters_by_domain group the masters by domain, the domains of replicas
+// are listed without master. The even share is the masters over the domains
+// rounded up.
+fn masters_by_domain(shards: &[Shard], zones: &Zones) -> (BTreeMap<String, Vec<String>>, usize) {
+    let mut domains: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for shard in shards {
+        for replica in &shard.replicas {
+            domains.entry(domain(zones, replica)).or_default();
+        }
+        domains
+            .entry(domain(zones, &shard.master))
+            .or_default()
+            .push(shard.master.clone());
+    }
+    let even = shards.len().div_ceil(domains.len().max(1));
+    (domains, even)
+}
+
+// audit report the copies of a shard sharing a host or a zone, then the
+// domains holding more masters than an even share.
+pub fn audit(shards: &[Shard], zones: &Zones) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for shard in shards {
+        let mut hosts: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut labels: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for addr in iter::once(&shard.master).chain(&shard.replicas) {
+            if let Some((host, port)) = split_addr(addr) {
+                if let Some(zone) = zones.zone(&host, &port) {
+                    labels
+                        .entry(format!("zone {}", zone))
+                        .or_default()
+                        .push(addr.clone());
+                }
+                hosts
+                    .entry(format!("host {}", host))
+                    .or_default()
+                    .push(addr.clone());
+            }
+        }
+        // a zone is only reported when it holds more than a host does.
+        let zones = labels
+            .into_iter()
+            .filter(|(_, nodes)| !hosts.values().any(|h| h == nodes));
+        for (domain, nodes) in hosts.clone().into_iter().chain(zones) {
+            if nodes.len() > 1 {
+                findings.push(Finding::Shared {
+                    master: shard.master.clone(),
+                    domain,
+                    nodes,
+                });
+            }
+        }
+    }
+    let (domains, even) = masters_by_domain(shards, zones);
+    for (domain, masters) in domains {
+        if masters.len() > even {
+            findings.push(Finding::Crowded {
+                domain,
+                masters,
+                even,
+            });
+        }
+    }
+    findings
+}
+
+// Repair is the plan to fix the placement, unsafe_shards the masters whose
+// shard still has no copy out of their domain.
+#[derive(Debug)]
+pub struct Repair {
+    pub plan: Plan,
+    pub unsafe_shards: Vec<String>,
+}
+
+// repair first promote replicas out of crowded domains with a manual
+// failover, the old master becomes a replica of the new one. Then every shard
+// without a replica out of the domain of its master takes one a shard can
+// spare, replicas which guard nothing first. Every move makes one more shard
+// safe so the plan is short.
+pub fn repair(shards: &[Shard], zones: &Zones) -> Repair {
+    let mut shards = shards.to_vec();
+    let mut plan = Plan::default();
+    loop {
+        let (domains, even) = masters_by_domain(&shards, zones);
+        let masters = |addr: &str| domains[&domain(zones, addr)].len();
+        let promote = shards
+            .iter()
+            .enumerate()
+            .filter(|(_, shard)| masters(&shard.master) > even)
+            .flat_map(|(idx, shard)| {
+                shard
+                    .replicas
+                    .iter()
+                    .enumerate()
+                    .map(move |(r, addr)| (idx, r, addr))
+            })
+            .filter(|(_, _, addr)| masters(addr) < even)
+            .min_by_key(|(_, _, addr)| masters(addr))
+            .map(|(idx, r, _)| (idx, r));
+        let (idx, r) = match promote {
+            Some(promote) => promote,
+            None => break,
+        };
+        let shard = &mut shards[idx];
+        let promoted = shard.replicas.remove(r);
+        plan.push(Step::Failover {
+            node: promoted.clone(),
+        });
+        let demoted = mem::replace(&mut shard.master, promoted);
+        shard.replicas.push(demoted);
+    }
+
+    let guard = |shard: &Shard, addr: &str| domain(zones, addr) != domain(zones, &shard.master);
+    let safe = |shard: &Shard| shard.replicas.iter().any(|r| guard(shard, r));
+    loop {
+        let mut moved = false;
+        for needy in 0..shards.len() {
+            if safe(&shards[needy]) {
+                continue;
+            }
+            let needy_domain = domain(zones, &shards[needy].master);
+            let donor = shards
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| *idx != needy)
+                .flat_map(|(idx, shard)| {
+                    shard
+                        .replicas
+                        .iter()
+                        .enumerate()
+                        .map(move |(r, addr)| (idx, shard, r, addr))
+                })
+                .filter(|(_, shard, _, addr)| {
+                    domain(zones, addr) != needy_domain
+                        && (!guard(shard, addr)
+                            || shard
+                                .replicas
+                                .iter()
+                                .any(|other| other != *addr && guard(shard, other)))
+                })
+                .min_by_key(|(_, shard, _, addr)| {
+                    (
+                        guard(shard, addr),
+                        shard.replicas.len() == 1,
+                        Reverse(shard.replicas.len()),
+                    )
+                })
+                .map(|(idx, _, r, _)| (idx, r));
+            if let Some((idx, r)) = donor {
+                let addr = shards[idx].replicas.remove(r);
+                plan.push(Step::Replicate {
+                    node: addr.clone(),
+                    master: shards[needy].master.clone(),
+                });
+                shards[needy].replicas.push(addr);
+                moved = true;
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+    Repair {
+        plan,
+        unsafe_shards: shards
+            .iter()
+            .filter(|shard| !safe(shard))
+            .map(|shard| shard.master.clone())
+            .collect(),
+    }
+}
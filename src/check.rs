@@ -0,0 +1,524 @@
+use cluster::{AsResult, Error, Node};
+use slots::ranges;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use topology::{Flags, Topology};
+
+#[cfg(test)]
+fn view(addr: &str, text: &str) -> View {
+    View {
+        addr: addr.to_string(),
+        topology: Topology::parse(text).unwrap(),
+    }
+}
+
+#[cfg(test)]
+const NODES_A: &str = "\
+aaaa 127.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-8191
+bbbb 127.0.0.1:7001@17001 master - 0 0 2 connected 8192-16383
+cccc 127.0.0.1:7002@17002 slave aaaa 0 0 1 connected
+dddd 127.0.0.1:7003@17003 slave bbbb 0 0 2 connected
+";
+
+#[test]
+fn test_check_healthy() {
+    let views = vec![
+        view("127.0.0.1:7000", NODES_A),
+        view(
+            "127.0.0.1:7001",
+            &NODES_A
+                .replace("myself,master - 0 0 1", "master - 0 0 1")
+                .replace("master - 0 0 2", "myself,master - 0 0 2"),
+        ),
+    ];
+    assert!(check(&views).is_empty());
+}
+
+#[test]
+fn test_check_problems() {
+    let text = "\
+aaaa 127.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-8000 [8001->-bbbb]
+bbbb 127.0.0.1:7001@17001 master - 0 0 2 connected 8100-16383
+cccc 127.0.0.1:7002@17002 slave,fail aaaa 0 0 1 disconnected
+eeee 127.0.0.1:7004@17004 handshake - 0 0 0 connected
+";
+    let other = "\
+aaaa 127.0.0.1:7000@17000 master - 0 0 1 connected 0-8000
+bbbb 127.0.0.1:7001@17001 myself,master - 0 0 2 connected 8000 8100-16383
+cccc 127.0.0.1:7002@17002 slave aaaa 0 0 1 connected
+";
+    let problems = check(&[view("127.0.0.1:7000", text), view("127.0.0.1:7001", other)]);
+    assert!(problems.contains(&Problem::UncoveredSlots(vec![(8001, 8099)])));
+    assert!(problems.contains(&Problem::MultipleOwners {
+        slot: 8000,
+        owners: vec!["127.0.0.1:7000".to_string(), "127.0.0.1:7001".to_string()],
+    }));
+    assert!(problems.contains(&Problem::OpenSlot {
+        node: "127.0.0.1:7000".to_string(),
+        slot: 8001,
+        state: "migrating",
+        peer: "127.0.0.1:7001".to_string(),
+    }));
+    assert!(problems.contains(&Problem::ViewMismatch {
+        node: "127.0.0.1:7001".to_string(),
+        other: "127.0.0.1:7000".to_string(),
+        slots: 1,
+        nodes: 1,
+    }));
+    assert!(problems.contains(&Problem::NoReplica {
+        master: "127.0.0.1:7000".to_string(),
+    }));
+    assert!(problems.contains(&Problem::NoReplica {
+        master: "127.0.0.1:7001".to_string(),
+    }));
+    assert!(problems.contains(&Problem::BadNode {
+        node: "127.0.0.1:7002".to_string(),
+        flags: Flags::parse("slave,fail"),
+        seen_by: "127.0.0.1:7000".to_string(),
+    }));
+    assert!(problems.contains(&Problem::BadNode {
+        node: "127.0.0.1:7004".to_string(),
+        flags: Flags::parse("handshake"),
+        seen_by: "127.0.0.1:7000".to_string(),
+    }));
+}
+
+#[test]
+fn test_duplicate_epochs() {
+    let text = "\
+bbbb 127.0.0.1:7001@17001 master - 0 0 3 connected 0-5000
+aaaa 127.0.0.1:7000@17000 myself,master - 0 0 3 connected 5001-10000
+cccc 127.0.0.1:7002@17002 master - 0 0 3 connected 10001-12000
+dddd 127.0.0.1:7003@17003 slave aaaa 0 0 3 connected
+eeee 127.0.0.1:7004@17004 master - 0 0 5 connected 12001-14000
+ffff 127.0.0.1:7005@17005 master - 0 0 5 connected 14001-16383
+gggg 127.0.0.1:7006@17006 master - 0 0 5 connected
+";
+    let topology = Topology::parse(text).unwrap();
+    assert_eq!(
+        duplicate_epochs(&topology),
+        vec![
+            (
+                3,
+                vec![
+                    "127.0.0.1:7000".to_string(),
+                    "127.0.0.1:7001".to_string(),
+                    "127.0.0.1:7002".to_string(),
+                ]
+            ),
+            (
+                5,
+                vec!["127.0.0.1:7004".to_string(), "127.0.0.1:7005".to_string()]
+            ),
+        ]
+    );
+    let problems = check(&[view("127.0.0.1:7000", text)]);
+    assert!(problems.contains(&Problem::DuplicateEpoch {
+        epoch: 5,
+        nodes: vec!["127.0.0.1:7004".to_string(), "127.0.0.1:7005".to_string()],
+    }));
+    assert!(duplicate_epochs(&Topology::parse(NODES_A).unwrap()).is_empty());
+}
+
+#[test]
+fn test_inspect_slot() {
+    let text = "\
+aaaa 127.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-8191 [100->-bbbb]
+bbbb 127.0.0.1:7001@17001 master - 0 0 2 connected 8192-16383
+";
+    let other = "\
+aaaa 127.0.0.1:7000@17000 master - 0 0 1 connected 0-99 101-8191
+bbbb 127.0.0.1:7001@17001 myself,master - 0 0 2 connected 8192-16383 [100-<-aaaa]
+";
+    let report = inspect_slot(
+        &[view("127.0.0.1:7000", text), view("127.0.0.1:7001", other)],
+        100,
+    );
+    assert_eq!(
+        report.owners,
+        vec![
+            (
+                "127.0.0.1:7000".to_string(),
+                Some("127.0.0.1:7000".to_string())
+            ),
+            ("127.0.0.1:7001".to_string(), None),
+        ]
+    );
+    assert_eq!(report.nodes(), vec!["127.0.0.1:7000", "127.0.0.1:7001"]);
+    assert_eq!(
+        report.to_string(),
+        "slot 100
+view of 127.0.0.1:7000: owned by 127.0.0.1:7000
+view of 127.0.0.1:7001: not covered
+127.0.0.1:7000 is migrating to 127.0.0.1:7001
+127.0.0.1:7001 is importing from 127.0.0.1:7000"
+    );
+}
+
+const CLUSTER_SLOTS: usize = 16384;
+
+// View is the topology as seen by the node at addr.
+#[derive(Debug)]
+pub struct View {
+    pub addr: String,
+    pub topology: Topology,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    // UncoveredSlots are inclusive slot ranges no master own in any view.
+    UncoveredSlots(Vec<(usize, usize)>),
+    MultipleOwners {
+        slot: usize,
+        owners: Vec<String>,
+    },
+    OpenSlot {
+        node: String,
+        slot: usize,
+        state: &'static str,
+        peer: String,
+    },
+    // ViewMismatch count the slots owned by another node and the nodes
+    // known by only one side compare to the first view.
+    ViewMismatch {
+        node: String,
+        other: String,
+        slots: usize,
+        nodes: usize,
+    },
+    NoReplica {
+        master: String,
+    },
+    BadNode {
+        node: String,
+        flags: Flags,
+        seen_by: String,
+    },
+    Unreachable {
+        node: String,
+        err: String,
+    },
+    // DuplicateEpoch is a config epoch shared by masters.
+    DuplicateEpoch {
+        epoch: u64,
+        nodes: Vec<String>,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::UncoveredSlots(ranges) => {
+                let ranges: Vec<String> = ranges
+                    .iter()
+                    .map(|(start, end)| {
+                        if start == end {
+                            start.to_string()
+                        } else {
+                            format!("{}-{}", start, end)
+                        }
+                    })
+                    .collect();
+                write!(f, "slots not covered: {}", ranges.join(","))
+            }
+            Problem::MultipleOwners { slot, owners } => {
+                write!(f, "slot {} claimed by {}", slot, owners.join(","))
+            }
+            Problem::OpenSlot {
+                node,
+                slot,
+                state,
+                peer,
+            } => write!(f, "slot {} is {} on {} with {}", slot, state, node, peer),
+            Problem::ViewMismatch {
+                node,
+                other,
+                slots,
+                nodes,
+            } => write!(
+                f,
+                "view of {} disagree with {}: {} slots, {} nodes differ",
+                node, other, slots, nodes
+            ),
+            Problem::NoReplica { master } => write!(f, "master {} has no replica", master),
+            Problem::BadNode {
+                node,
+                flags,
+                seen_by,
+            } => write!(f, "node {} is {} seen by {}", node, flags, seen_by),
+            Problem::Unreachable { node, err } => write!(f, "node {} unreachable: {}", node, err),
+            Problem::DuplicateEpoch { epoch, nodes } => {
+                write!(f, "config epoch {} shared by {}", epoch, nodes.join(","))
+            }
+        }
+    }
+}
+
+// SlotReport is the state of one slot in every view.
+#[derive(Debug, PartialEq)]
+pub struct SlotReport {
+    pub slot: usize,
+    // owners is the owner of the slot in each view, None when not covered.
+    pub owners: Vec<(String, Option<String>)>,
+    // open is the node, migrating or importing, and the peer as reported by
+    // the node itself.
+    pub open: Vec<(String, &'static str, String)>,
+}
+
+impl SlotReport {
+    // nodes are the nodes that may hold keys of the slot.
+    pub fn nodes(&self) -> Vec<&str> {
+        let nodes: BTreeSet<&str> = self
+            .owners
+            .iter()
+            .filter_map(|(_, owner)| owner.as_ref().map(|x| x.as_str()))
+            .chain(self.open.iter().map(|(node, _, _)| node.as_str()))
+            .collect();
+        nodes.into_iter().collect()
+    }
+}
+
+impl fmt::Display for SlotReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "slot {}", self.slot)?;
+        for (view, owner) in &self.owners {
+            match owner {
+                Some(owner) => write!(f, "\nview of {}: owned by {}", view, owner)?,
+                None => write!(f, "\nview of {}: not covered", view)?,
+            }
+        }
+        for (node, state, peer) in &self.open {
+            let dir = if *state == "migrating" { "to" } else { "from" };
+            write!(f, "\n{} is {} {} {}", node, state, dir, peer)?;
+        }
+        Ok(())
+    }
+}
+
+// inspect_slot collect the owner of slot in every view and its open states.
+pub fn inspect_slot(views: &[View], slot: usize) -> SlotReport {
+    let owners = views
+        .iter()
+        .map(|view| {
+            let owner = view
+                .topology
+                .masters()
+                .find(|m| m.slots.contains(&slot))
+                .map(|m| m.addr.clone());
+            (view.addr.clone(), owner)
+        })
+        .collect();
+    let mut open = Vec::new();
+    for view in views {
+        let myself = match view.topology.myself() {
+            Some(myself) => myself,
+            None => continue,
+        };
+        let addr_of = |id: &String| {
+            view.topology
+                .node(id)
+                .map_or(id.clone(), |n| n.addr.clone())
+        };
+        if let Some(peer) = myself.migrating.get(&slot) {
+            open.push((view.addr.clone(), "migrating", addr_of(peer)));
+        }
+        if let Some(peer) = myself.importing.get(&slot) {
+            open.push((view.addr.clone(), "importing", addr_of(peer)));
+        }
+    }
+    SlotReport { slot, owners, open }
+}
+
+// gather collect the view of every available node known by seed. Nodes that
+// can't be queried are returned as problems.
+pub fn gather(seed: &Node) -> AsResult<(Vec<View>, Vec<Problem>)> {
+    let mut views = Vec::new();
+    let mut problems = Vec::new();
+    for node in seed.nodes()? {
+        if !node.is_available() {
+            continue;
+        }
+        match node.topology() {
+            Ok(topology) => views.push(View {
+                addr: node.addr(),
+                topology,
+            }),
+            Err(err) => problems.push(Problem::Unreachable {
+                node: node.addr(),
+                err: err.to_string(),
+            }),
+        }
+    }
+    if views.is_empty() {
+        return Err(Error::Precondition(format!(
+            "no view could be collected from {}",
+            seed.addr()
+        )));
+    }
+    Ok((views, problems))
+}
+
+// check validate the cluster invariants over all views, it never talks to the
+// cluster itself.
+pub fn check(views: &[View]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    if views.is_empty() {
+        return problems;
+    }
+    let addrs: HashMap<&str, &str> = views
+        .iter()
+        .flat_map(|v| v.topology.nodes.iter())
+        .map(|n| (&*n.id, &*n.addr))
+        .collect();
+    let addr_of = |id: &str| addrs.get(id).cloned().unwrap_or(id).to_string();
+
+    // slots claimed by a master in its own view.
+    let mut owners: BTreeMap<usize, BTreeSet<String>> = BTreeMap::new();
+    let mut covered = vec![false; CLUSTER_SLOTS];
+    for view in views {
+        for entry in view.topology.masters() {
+            for slot in &entry.slots {
+                covered[*slot] = true;
+                if entry.is_myself() {
+                    owners.entry(*slot).or_default().insert(entry.addr.clone());
+                }
+            }
+        }
+    }
+    let uncovered = ranges(
+        &covered
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !**c)
+            .map(|(slot, _)| slot)
+            .collect::<Vec<usize>>(),
+    );
+    if !uncovered.is_empty() {
+        problems.push(Problem::UncoveredSlots(uncovered));
+    }
+    for (slot, owner) in owners {
+        if owner.len() > 1 {
+            problems.push(Problem::MultipleOwners {
+                slot,
+                owners: owner.into_iter().collect(),
+            });
+        }
+    }
+
+    for view in views {
+        if let Some(myself) = view.topology.myself() {
+            let open = myself
+                .migrating
+                .iter()
+                .map(|(slot, peer)| (slot, "migrating", peer))
+                .chain(
+                    myself
+                        .importing
+                        .iter()
+                        .map(|(slot, peer)| (slot, "importing", peer)),
+                );
+            for (slot, state, peer) in open {
+                problems.push(Problem::OpenSlot {
+                    node: view.addr.clone(),
+                    slot: *slot,
+                    state,
+                    peer: addr_of(peer),
+                });
+            }
+        }
+    }
+
+    let first = &views[0];
+    let first_owner = slot_owner(&first.topology);
+    let first_nodes: BTreeSet<&str> = first.topology.nodes.iter().map(|n| &*n.id).collect();
+    for view in &views[1..] {
+        let owner = slot_owner(&view.topology);
+        let slots = (0..CLUSTER_SLOTS)
+            .filter(|slot| owner.get(slot) != first_owner.get(slot))
+            .count();
+        let known: BTreeSet<&str> = view.topology.nodes.iter().map(|n| &*n.id).collect();
+        let nodes = known.symmetric_difference(&first_nodes).count();
+        if slots != 0 || nodes != 0 {
+            problems.push(Problem::ViewMismatch {
+                node: view.addr.clone(),
+                other: first.addr.clone(),
+                slots,
+                nodes,
+            });
+        }
+    }
+
+    for master in first.topology.masters() {
+        if master.slots.is_empty() {
+            continue;
+        }
+        let replicas = first
+            .topology
+            .nodes
+            .iter()
+            .filter(|n| n.master.as_ref() == Some(&master.id) && n.flags.is_available())
+            .count();
+        if replicas == 0 {
+            problems.push(Problem::NoReplica {
+                master: master.addr.clone(),
+            });
+        }
+    }
+
+    for (epoch, nodes) in duplicate_epochs(&first.topology) {
+        problems.push(Problem::DuplicateEpoch { epoch, nodes });
+    }
+
+    let mut reported = BTreeSet::new();
+    for view in views {
+        for entry in &view.topology.nodes {
+            if entry.flags.is_available() || !reported.insert(entry.id.clone()) {
+                continue;
+            }
+            problems.push(Problem::BadNode {
+                node: if entry.addr.is_empty() || entry.addr.starts_with(':') {
+                    entry.id.clone()
+                } else {
+                    entry.addr.clone()
+                },
+                flags: entry.flags.clone(),
+                seen_by: view.addr.clone(),
+            });
+        }
+    }
+    problems
+}
+
+// duplicate_epochs return the config epochs shared by masters with their
+// addrs ordered by node id, as redis does when it resolves a collision.
+// Masters without slots can't claim anything and are left out.
+pub fn duplicate_epochs(topology: &Topology) -> Vec<(u64, Vec<String>)> {
+    let mut epochs: BTreeMap<u64, Vec<(&str, &str)>> = BTreeMap::new();
+    for master in topology.masters().filter(|m| !m.slots.is_empty()) {
+        epochs
+            .entry(master.config_epoch)
+            .or_default()
+            .push((&master.id, &master.addr));
+    }
+    epochs
+        .into_iter()
+        .filter(|(_, nodes)| nodes.len() > 1)
+        .map(|(epoch, mut nodes)| {
+            nodes.sort();
+            (
+                epoch,
+                nodes
+                    .into_iter()
+                    .map(|(_, addr)| addr.to_string())
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+fn slot_owner(topology: &Topology) -> HashMap<usize, &str> {
+    topology
+        .masters()
+        .flat_map(|n| n.slots.iter().map(move |slot| (*slot, &*n.id)))
+        .collect()
+}
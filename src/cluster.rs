@@ -1,19 +1,36 @@
-use redis::Connection;
+use conn;
+use locate::KeyState;
+use migrate::{self, Collection, Conflict, MigrateOpts};
+use plan::{Plan, Step};
+use progress;
+use redis::{Cmd, Connection, FromRedisValue, Pipeline, RedisError};
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::error;
 use std::fmt;
-use std::rc::Rc;
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs};
 use std::result;
+use std::slice;
 use std::str;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use topology::{self, Flags, LinkState, NodeEntry, Topology};
 use util;
-pub static COLON_STR: &'static str = ":";
 #[test]
 fn test_node_init() {
     let node = Node::new(b"127.0.0.1:8888").unwrap();
     assert_eq!(node.ip, "127.0.0.1");
     assert_eq!(node.port, "8888");
+    let node = Node::new(b"[::1]:8888").unwrap();
+    assert_eq!(node.ip, "::1");
+    assert_eq!(node.addr(), "[::1]:8888");
+    let node = Node::new(b"redis-1.example.com:8888").unwrap();
+    assert_eq!(node.ip, "redis-1.example.com");
+    assert!(Node::new(b"127.0.0.1").is_err());
 }
 #[test]
+#[allow(clippy::vec_init_then_push, clippy::bool_assert_comparison)]
 fn test_consistency() {
     let mut nodes = Vec::new();
     nodes.push(Node::new(b"127.0.0.1:7000").unwrap());
@@ -22,7 +39,56 @@ fn test_consistency() {
     nodes.push(Node::new(b"127.0.0.1:7003").unwrap());
     nodes.push(Node::new(b"127.0.0.1:7004").unwrap());
     let cluster = Cluster::new(nodes);
-    assert_eq!(cluster.consistency(), true);
+    assert_eq!(cluster.consistency().unwrap(), true);
+}
+#[test]
+fn test_node_send() {
+    fn is_send<T: Send>() {}
+    is_send::<Node>();
+    is_send::<Error>();
+}
+
+#[test]
+fn test_plans() {
+    let text = "\
+aaaa 127.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-10000
+bbbb 127.0.0.1:7001@17001 master - 0 0 2 connected 10001-16383
+cccc 127.0.0.1:7002@17002 master - 0 0 3 connected
+dddd 127.0.0.1:7003@17003 slave aaaa 0 0 1 connected
+";
+    let topology = Topology::parse(text).unwrap();
+    let nodes = topology
+        .nodes
+        .iter()
+        .map(|entry| Node::from_entry(entry).unwrap())
+        .collect();
+    let cluster = Cluster::new(nodes);
+
+    let plan = cluster.reshard_plan().unwrap();
+    let moved = |src: &str, dst: &str| {
+        plan.steps
+            .iter()
+            .filter(|step| match step {
+                Step::MigrateSlot { src: s, dst: d, .. } => s == src && d == dst,
+                _ => false,
+            })
+            .count()
+    };
+    assert_eq!(moved("127.0.0.1:7000", "127.0.0.1:7002"), 10001 - 5462);
+    assert_eq!(moved("127.0.0.1:7001", "127.0.0.1:7002"), 6383 - 5461);
+    assert_eq!(plan.steps.len(), 5461);
+
+    let del = cluster.node("127.0.0.1:7001").unwrap();
+    let plan = cluster.delete_plan(&[del]).unwrap();
+    assert_eq!(plan.steps.len(), 6383 + 3);
+    assert_eq!(
+        plan.steps.last(),
+        Some(&Step::Forget {
+            node: "127.0.0.1:7003".to_string(),
+            target: "127.0.0.1:7001".to_string(),
+            id: "bbbb".to_string(),
+        })
+    );
 }
 #[derive(Debug, PartialEq, Clone)]
 pub enum Role {
@@ -40,8 +106,15 @@ impl fmt::Debug for Node {
         let slot_num = self.slots.borrow().len();
         write!(
             f,
-            "Node{{name: {:?} ,ip: {},port: {},slots: {},self:{:?},role:{:?},slaveof:{:?} }}",
-            self.name, self.ip, self.port, slot_num, self.myself, self.role, self.slaveof
+            "Node{{name: {:?} ,ip: {},port: {},slots: {},self:{:?},role:{:?},slaveof:{:?},flags:{} }}",
+            self.name,
+            self.ip,
+            self.port,
+            slot_num,
+            self.myself,
+            self.role,
+            self.slaveof,
+            self.flags
         )
     }
 }
@@ -60,167 +133,490 @@ impl Cluster {
         self.nodes.len()
     }
 
-    pub fn consistency(&self) -> bool {
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn consistency(&self) -> AsResult<bool> {
         let mut node_slot: HashMap<usize, Node> = HashMap::new();
         for node in &self.nodes {
+            if !node.is_available() {
+                println!("skip unavailable node {} [{}]", node.addr(), node.flags);
+                continue;
+            }
             let mut slot_num = 0;
-            let nodes = node.nodes();
+            let nodes = node.nodes()?;
             for node in nodes.into_iter() {
                 for slot in node.slots.clone().into_inner() {
                     let sv = node_slot.entry(slot).or_insert_with(|| node.clone());
                     if *sv != node {
-                        return false;
+                        return Ok(false);
                     }
                     slot_num += 1;
                 }
             }
 
             if slot_num != 16384 {
-                return false;
+                return Ok(false);
             }
         }
         println!("cluster consistence, all slots coverd");
-        true
+        Ok(true)
     }
 
-    pub fn check(&self) -> Result<(), Error> {
-        for node in &self.nodes {
-            let nodes_info = node.info();
-            assert_eq!(
-                nodes_info.get("cluster_known_nodes").cloned(),
-                Some("1".to_string())
-            );
-        }
-        Ok(())
-    }
-
-    pub fn delete_node(&self, del_node: &Node) {
-        if del_node.is_master() {
-            let nodes: Vec<&Node> = self
-                .nodes
-                .iter()
-                .filter(|&x| x.role == Some(Role::Master) && x.name != del_node.name)
-                .collect();
-            let slots = del_node.slots.borrow();
-            let slot_count = slots.len();
-            let mut dispatch = util::divide(slot_count, nodes.len());
+    // delete_plan move the slots of the deleted masters to the remaining
+    // masters, then let the other nodes forget them.
+    pub fn delete_plan(&self, del_nodes: &[&Node]) -> AsResult<Plan> {
+        let mut plan = Plan::default();
+        let deleted = |node: &Node| del_nodes.iter().any(|x| x.name == node.name);
+        let masters: Vec<&Node> = self
+            .nodes
+            .iter()
+            .filter(|&x| x.role == Some(Role::Master) && !deleted(x) && x.is_available())
+            .collect();
+        for del_node in del_nodes.iter().filter(|x| x.is_master()) {
+            let slots = del_node.slots();
+            if slots.is_empty() {
+                continue;
+            }
+            if masters.is_empty() {
+                return Err(Error::Precondition(format!(
+                    "no available master to take slots of {}",
+                    del_node.addr()
+                )));
+            }
+            let mut dispatch = util::divide(slots.len(), masters.len());
             let mut start = 0;
-            for node in nodes {
-                println!("start migrate from {:?} to {:?}", del_node, node);
+            for node in &masters {
                 let count = dispatch.pop().unwrap();
-                let migrate = &slots[start..start + count];
-                // todo:MIGRATE DATA
-                for slot in migrate.iter() {
-                    migrate_slot(del_node, node, *slot);
-                }
+                plan.migrate_slots(del_node, node, &slots[start..start + count]);
                 start += count;
-                println!("stop migrate from {:?} to {:?}", del_node, node);
             }
         }
 
-        let forget = |node: &Node| {
+        for del_node in del_nodes {
             for n in &self.nodes {
-                if n.name == node.name {
+                if deleted(n) {
                     continue;
                 }
-                if Some(node.name.to_string()) == n.slaveof {
+                if Some(del_node.name.to_string()) == n.slaveof {
                     // todo forget slav
                     continue;
                 }
-                println!("node {:?} forget {:?}", n, node);
-                n.forget(&node);
+                if !n.is_available() {
+                    println!("skip forget on unavailable node {} [{}]", n.addr(), n.flags);
+                    continue;
+                }
+                plan.push(Step::Forget {
+                    node: n.addr(),
+                    target: del_node.addr(),
+                    id: del_node.name.clone(),
+                });
             }
-        };
-        forget(del_node);
+        }
+        Ok(plan)
     }
 
     pub fn node(&self, node: &str) -> Option<&Node> {
-        for n in &self.nodes {
-            if n.addr() == node {
-                return Some(n);
-            }
-        }
-        None
+        self.nodes.iter().find(|n| n.addr() == node)
     }
 
-    pub fn fill_slots(&self) {
+    pub fn fill_slots(&self) -> AsResult<()> {
         let slots: HashSet<usize> = self
             .nodes
             .iter()
             .filter(|x| x.is_master())
-            .map(|x| x.slots.clone().into_inner())
-            .flatten()
+            .flat_map(|x| x.slots.clone().into_inner())
             .collect();
         let all_slots: HashSet<usize> = (1..16384).collect();
         let miss = all_slots
             .difference(&slots)
             .cloned()
             .collect::<Vec<usize>>();
-        let mut dist = util::divide(miss.len(), self.nodes.len());
+        if miss.is_empty() {
+            return Ok(());
+        }
+        let masters: Vec<&Node> = self
+            .nodes
+            .iter()
+            .filter(|x| x.is_master() && x.is_available())
+            .collect();
+        if masters.is_empty() {
+            return Err(Error::Precondition(
+                "no available master to fill missing slots".to_string(),
+            ));
+        }
+        let mut dist = util::divide(miss.len(), masters.len());
         let mut idx = 0;
-        for node in self.nodes.iter().filter(|x| x.is_master()) {
+        for node in masters {
             let num = dist.pop().unwrap();
             if num == 0 {
                 continue;
             }
             let slots = &miss[idx..idx + num];
-            node.add_slots(slots);
+            node.add_slots(slots)?;
             idx += num;
         }
+        Ok(())
     }
 
-    pub fn fix_slots(&self) {
+    pub fn fix_slots(&self) -> AsResult<()> {
         for master in self.nodes.iter().filter(|x| x.is_master()) {
-            master.fix_node();
+            if !master.is_available() {
+                println!(
+                    "skip fix unavailable node {} [{}]",
+                    master.addr(),
+                    master.flags
+                );
+                continue;
+            }
+            master.fix_node()?;
         }
+        Ok(())
     }
 
-    pub fn reshard(&self) {
-        let master: Vec<Node> = self
-            .nodes
-            .iter()
-            .filter(|x| x.is_master())
-            .cloned()
-            .collect();
-        let mut slots = vec![];
-        let dist: Vec<(Node, usize)> = master
-            .iter()
-            .cloned()
-            .zip(util::divide(16384, master.len()))
-            .collect();
+    // reshard_plan even the slot count of all masters.
+    pub fn reshard_plan(&self) -> AsResult<Plan> {
+        let master: Vec<&Node> = self.nodes.iter().filter(|x| x.is_master()).collect();
+        if master.is_empty() {
+            return Err(Error::Precondition("no master to reshard".to_string()));
+        }
+        let share = util::divide(16384, master.len());
+        let dist: Vec<(&Node, usize)> = master.into_iter().zip(share).collect();
+        let mut spare = vec![];
         for (node, num) in &dist {
-            let delta = node.slots.borrow().len() as i64 - *num as i64;
-            if delta > 0 {
-                for _i in 0..delta {
-                    slots.push((node.clone(), node.slots.borrow_mut().pop().unwrap()))
-                }
+            let slots = node.slots();
+            if slots.len() > *num {
+                spare.extend(slots[*num..].iter().rev().map(|slot| (*node, *slot)));
             }
         }
+        let mut plan = Plan::default();
         for (node, num) in &dist {
-            let mut delta = node.slots.borrow().len() as i64 - *num as i64;
-            while delta < 0 {
-                let (src, slot) = slots.pop().unwrap();
-                migrate_slot(&src, node, slot);
-                delta += 1;
+            for _ in node.slots().len()..*num {
+                let (src, slot) = match spare.pop() {
+                    Some(item) => item,
+                    None => break,
+                };
+                plan.migrate_slots(src, node, &[slot]);
             }
         }
+        Ok(plan)
     }
 }
 
-pub fn migrate_slot(src: &Node, dst: &Node, slot: usize) {
+pub fn migrate_slot(src: &Node, dst: &Node, slot: usize) -> AsResult<()> {
     println!(
         "migrate slot{:?} from {:?} to {:?}",
         slot,
         src.addr(),
         dst.addr()
     );
-    dst.setslot("IMPORTING", dst.name.clone(), slot);
-    src.setslot("MIGRATING", src.name.clone(), slot);
-    while let Some(key) = src.keysinslot(slot) {
-        src.migrate(&*dst.ip, &*dst.port, key);
+    dst.setslot("IMPORTING", src.name.clone(), slot)?;
+    src.setslot("MIGRATING", dst.name.clone(), slot)?;
+    migrate_keys(src, dst, slot)?;
+    src.setslot("NODE", dst.name.clone(), slot)?;
+    dst.setslot("NODE", dst.name.clone(), slot)
+}
+
+// finish_slot complete a move of slot from src to dst interrupted at any step.
+pub fn finish_slot(src: &Node, dst: &Node, slot: usize) -> AsResult<()> {
+    let src_view = src.topology()?;
+    if src_view.myself().is_some_and(|n| n.slots.contains(&slot)) {
+        return migrate_slot(src, dst, slot);
+    }
+    let dst_view = dst.topology()?;
+    match dst_view.myself() {
+        // src already gave the slot away, dst only miss its own SETSLOT NODE.
+        Some(me) if me.importing.contains_key(&slot) => dst.setslot("NODE", dst.name.clone(), slot),
+        Some(me) if me.slots.contains(&slot) => Ok(()),
+        _ => Err(Error::Precondition(format!(
+            "slot {} is owned by neither {} nor {}",
+            slot,
+            src.addr(),
+            dst.addr()
+        ))),
+    }
+}
+
+// rollback_slot give slot and its keys back to src. A move not started yet is
+// left alone, otherwise it is finished then the slot migrate back, dst can't
+// MIGRATE keys of a slot it neither own nor migrate.
+pub fn rollback_slot(src: &Node, dst: &Node, slot: usize) -> AsResult<()> {
+    let src_view = src.topology()?;
+    let dst_view = dst.topology()?;
+    let untouched = src_view
+        .myself()
+        .is_some_and(|n| n.slots.contains(&slot) && !n.migrating.contains_key(&slot))
+        && dst_view
+            .myself()
+            .is_some_and(|n| !n.importing.contains_key(&slot));
+    if untouched {
+        return Ok(());
+    }
+    finish_slot(src, dst, slot)?;
+    migrate_slot(dst, src, slot)
+}
+
+// migrate_keys move all keys of slot from src to dst, the slot must be
+// migrating on src and importing on dst. The keys skipped by Conflict::Skip
+// stay on src and make the move fail once all other keys are moved.
+pub fn migrate_keys(src: &Node, dst: &Node, slot: usize) -> AsResult<()> {
+    let opts = migrate::opts();
+    let mut backoff = migrate::Backoff::default();
+    let mut skipped: HashSet<String> = HashSet::new();
+    if opts.copy {
+        // copied keys stay on src, each round read past the keys already seen
+        // so a large slot is never listed at once.
+        let mut copied: HashSet<String> = HashSet::new();
+        loop {
+            let keys: Vec<String> = src
+                .keysinslot(slot, opts.batch + copied.len() + skipped.len())?
+                .into_iter()
+                .filter(|k| !copied.contains(k) && !skipped.contains(k))
+                .collect();
+            if keys.is_empty() {
+                break;
+            }
+            let skip = migrate_batch(src, dst, slot, &keys, &opts, &mut backoff)?;
+            copied.extend(keys.into_iter().filter(|k| !skip.contains(k)));
+            skipped.extend(skip);
+        }
+        let copied: Vec<String> = copied.into_iter().collect();
+        for batch in copied.chunks(opts.batch) {
+            backoff.wait(src)?;
+            hand_over(src, dst, batch, &opts)?;
+        }
+    }
+    // keys written meanwhile are moved as usual.
+    let opts = MigrateOpts {
+        copy: false,
+        ..opts
+    };
+    loop {
+        let keys: Vec<String> = src
+            .keysinslot(slot, opts.batch + skipped.len())?
+            .into_iter()
+            .filter(|k| !skipped.contains(k))
+            .collect();
+        if keys.is_empty() {
+            break;
+        }
+        skipped.extend(migrate_batch(src, dst, slot, &keys, &opts, &mut backoff)?);
+    }
+    if !skipped.is_empty() {
+        return Err(Error::Precondition(format!(
+            "{} keys of slot {} skipped and left on {}, the slot stays open",
+            skipped.len(),
+            slot,
+            src.addr()
+        )));
+    }
+    Ok(())
+}
+
+// migrate_batch move keys from src to dst and return the keys skipped. A
+// failed batch is retried key by key so only the bad keys are subject to the
+// conflict policy.
+fn migrate_batch(
+    src: &Node,
+    dst: &Node,
+    slot: usize,
+    keys: &[String],
+    opts: &MigrateOpts,
+    backoff: &mut migrate::Backoff,
+) -> AsResult<Vec<String>> {
+    backoff.wait(src)?;
+    let sizes = if opts.large_key.is_some() || opts.bytes_per_sec.is_some() {
+        src.memory_usage(keys)?
+    } else {
+        vec![0; keys.len()]
+    };
+    let (count, bytes) = (keys.len(), sizes.iter().sum());
+    migrate::throttle(count, bytes);
+    let size: HashMap<&String, u64> = keys.iter().zip(sizes.iter().cloned()).collect();
+    let (large, keys) = migrate::split_large(keys, &sizes, opts.large_key);
+    let mut skipped = Vec::new();
+    let mut skip = |key: &String, err: Error| {
+        if opts.conflict != Conflict::Skip {
+            println!("key {} of slot {} can't be moved", key, slot);
+            return Err(err);
+        }
+        println!("skip key {} of slot {}: {}", key, slot, err);
+        skipped.push(key.clone());
+        Ok(())
+    };
+    for key in &large {
+        if let Err(err) = move_large(src, dst, key, opts) {
+            skip(key, err)?;
+        }
+    }
+    let mut keys = keys;
+    let copy = opts.copy && opts.conflict != Conflict::Replace;
+    if copy {
+        // keys already on dst are sorted out first, so a key found on dst
+        // after a failed batch is one the batch copied.
+        let exists = dst.exists(&keys)?;
+        let (busy, free): (Vec<_>, Vec<_>) = keys.into_iter().zip(exists).partition(|(_, e)| *e);
+        for (key, _) in busy {
+            let err = Error::Precondition(format!("key {} already exists on {}", key, dst.addr()));
+            skip(&key, err)?;
+        }
+        keys = free.into_iter().map(|(key, _)| key).collect();
+    }
+    if !keys.is_empty() && src.migrate(dst, &keys, opts).is_err() {
+        // copied keys stay on src, they are not copied again.
+        let done = if copy {
+            dst.exists(&keys)?
+        } else {
+            vec![false; keys.len()]
+        };
+        for (key, done) in keys.iter().zip(done) {
+            if done {
+                continue;
+            }
+            if let Err(err) = src.migrate(dst, slice::from_ref(key), opts) {
+                skip(key, err)?;
+            }
+        }
+    }
+    let skipped_bytes: u64 = skipped.iter().filter_map(|key| size.get(key)).sum();
+    progress::moved(count - skipped.len(), bytes - skipped_bytes)?;
+    Ok(skipped)
+}
+
+// hand_over move the keys copied to dst for good while the slot is still
+// migrating, src send the value it holds now so the writes made since the
+// copy are kept. The copies of keys deleted on src meanwhile are dropped.
+fn hand_over(src: &Node, dst: &Node, keys: &[String], opts: &MigrateOpts) -> AsResult<()> {
+    let opts = MigrateOpts {
+        copy: false,
+        conflict: Conflict::Replace,
+        ..opts.clone()
+    };
+    let exists = src.exists(keys)?;
+    let (live, gone): (Vec<_>, Vec<_>) = keys.iter().cloned().zip(exists).partition(|(_, e)| *e);
+    let gone: Vec<String> = gone.into_iter().map(|(key, _)| key).collect();
+    if !gone.is_empty() {
+        dst.unlink(&gone)?;
+    }
+    let live: Vec<String> = live.into_iter().map(|(key, _)| key).collect();
+    if !live.is_empty() && src.migrate(dst, &live, &opts).is_err() {
+        // large keys may need the large key timeout.
+        let opts = MigrateOpts {
+            timeout: opts.large_timeout.max(opts.timeout),
+            ..opts
+        };
+        for key in &live {
+            src.migrate(dst, slice::from_ref(key), &opts)?;
+        }
+    }
+    Ok(())
+}
+
+// move_large move a key above the large key size alone with the large key
+// timeout, or rebuild it on dst chunk by chunk when it is a collection and
+// chunks are enabled. A rebuilt key must not be written meanwhile.
+fn move_large(src: &Node, dst: &Node, key: &str, opts: &MigrateOpts) -> AsResult<()> {
+    let kind: String = src.query(redis::cmd("TYPE").arg(key))?;
+    match (opts.chunk, Collection::parse(&kind)) {
+        (Some(chunk), Some(kind)) => rebuild_key(src, dst, key, kind, chunk, opts),
+        _ => {
+            println!("migrate large key {} alone", key);
+            let opts = MigrateOpts {
+                timeout: opts.large_timeout,
+                ..opts.clone()
+            };
+            src.migrate(dst, &[key.to_string()], &opts)
+        }
+    }
+}
+
+// rebuild_key copy the elements of the collection key to dst chunk at a time,
+// then its ttl, and remove it from src unless the move is a copy.
+fn rebuild_key(
+    src: &Node,
+    dst: &Node,
+    key: &str,
+    kind: Collection,
+    chunk: usize,
+    opts: &MigrateOpts,
+) -> AsResult<()> {
+    println!("rebuild large {:?} {} on {}", kind, key, dst.addr());
+    if dst.exists(&[key.to_string()])?[0] {
+        if opts.conflict != Conflict::Replace {
+            return Err(Error::Precondition(format!(
+                "key {} already exists on {}",
+                key,
+                dst.addr()
+            )));
+        }
+        let _: () = dst.asking(redis::cmd("UNLINK").arg(key))?;
+    }
+    if let Err(err) = copy_elements(src, dst, key, kind, chunk) {
+        // a half built key is worse than none.
+        let _: AsResult<()> = dst.asking(redis::cmd("UNLINK").arg(key));
+        return Err(err);
+    }
+    let ttl: i64 = src.query(redis::cmd("PTTL").arg(key))?;
+    if ttl > 0 {
+        let _: () = dst.asking(redis::cmd("PEXPIRE").arg(key).arg(ttl))?;
+    }
+    if opts.copy {
+        return Ok(());
+    }
+    src.query(redis::cmd("UNLINK").arg(key))
+}
+
+fn copy_elements(
+    src: &Node,
+    dst: &Node,
+    key: &str,
+    kind: Collection,
+    chunk: usize,
+) -> AsResult<()> {
+    let add = |items: Vec<Vec<u8>>| -> AsResult<()> {
+        let mut cmd = redis::cmd(kind.add());
+        cmd.arg(key).arg(migrate::add_args(kind, items));
+        dst.asking(&cmd)
+    };
+    match kind.scan() {
+        Some(scan) => {
+            let mut cursor = 0u64;
+            loop {
+                let (next, items): (u64, Vec<Vec<u8>>) = src.query(
+                    redis::cmd(scan)
+                        .arg(key)
+                        .arg(cursor)
+                        .arg("COUNT")
+                        .arg(chunk),
+                )?;
+                if !items.is_empty() {
+                    add(items)?;
+                }
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
+            }
+        }
+        None => {
+            let mut start = 0;
+            loop {
+                let items: Vec<Vec<u8>> = src.query(
+                    redis::cmd("LRANGE")
+                        .arg(key)
+                        .arg(start)
+                        .arg(start + chunk - 1),
+                )?;
+                if items.is_empty() {
+                    break;
+                }
+                start += items.len();
+                add(items)?;
+            }
+        }
     }
-    src.setslot("NODE", dst.name.clone(), slot);
-    dst.setslot("NODE", dst.name.clone(), slot);
+    Ok(())
 }
 
 #[derive(Clone)]
@@ -228,6 +624,8 @@ pub struct Node {
     pub name: String,
     pub ip: String,
     pub port: String,
+    // hostname is the name announced in CLUSTER NODES, connections still use ip.
+    pub hostname: Option<String>,
     role: Option<Role>,
     myself: Option<bool>,
     pub slaveof: Option<String>,
@@ -235,48 +633,109 @@ pub struct Node {
     slots: RefCell<Vec<usize>>,
     migrating: HashMap<usize, String>,
     importing: HashMap<usize, String>,
-    conn: Rc<Option<Connection>>,
+    pub flags: Flags,
+    pub link_state: LinkState,
+    pub ping_sent: u64,
+    pub pong_recv: u64,
+    pub config_epoch: u64,
+    // conn is shared by the clones of a node, Node is Send so it can be moved
+    // to a worker, workers open their own nodes to run in parallel.
+    conn: Arc<Mutex<Option<Connection>>>,
 }
 
 impl Node {
+    // new accept host:port, host is a name or an IP, IPv6 may be enclosed in brackets.
     pub fn new(addr: &[u8]) -> AsResult<Node> {
         let content = String::from_utf8_lossy(addr);
-        let items: Vec<&str> = content.split(COLON_STR).collect();
-        if items.len() != 2 {
-            Err(Error::BadAddr)
-        } else {
-            let ip = items[0];
-            let port = items[1];
-
-            let con = if ip != "" {
-                let addr = "redis://".to_string() + ip + ":" + port;
-                Some(
-                    redis::Client::open(&*addr)
-                        .unwrap()
-                        .get_connection()
-                        .unwrap(),
-                )
-            } else {
-                None
-            };
-            Ok(Node {
-                name: str::from_utf8(addr).unwrap().to_string(),
+        match topology::split_addr(&content) {
+            None => Err(Error::BadAddr),
+            Some((ip, port)) => Ok(Node {
+                name: content.to_string(),
                 role: None,
-                port: port.to_string(),
-                ip: ip.to_string(),
+                port,
+                ip,
+                hostname: None,
                 slaveof: None,
                 myself: None,
                 nodes: RefCell::new(HashMap::new()),
                 slots: RefCell::new(vec![]),
                 migrating: HashMap::new(),
                 importing: HashMap::new(),
-                conn: Rc::new(con),
-            })
+                flags: Flags::default(),
+                link_state: LinkState::default(),
+                ping_sent: 0,
+                pong_recv: 0,
+                config_epoch: 0,
+                conn: Arc::new(Mutex::new(None)),
+            }),
         }
     }
 
-    pub fn connect(&mut self) {
-        let nodes = self.nodes();
+    // from_entry build node from a parsed CLUSTER NODES line without connecting to it.
+    pub fn from_entry(entry: &NodeEntry) -> AsResult<Node> {
+        let mut node = Node::new(entry.addr.as_bytes())?;
+        node.name = entry.id.clone();
+        node.hostname = entry.hostname.clone();
+        node.role = Some(if entry.is_master() {
+            Role::Master
+        } else {
+            Role::Slave
+        });
+        if entry.is_myself() {
+            node.myself = Some(true);
+        }
+        node.slaveof = entry.master.clone();
+        node.slots = RefCell::new(entry.slots.clone());
+        node.migrating = entry.migrating.clone();
+        node.importing = entry.importing.clone();
+        node.flags = entry.flags.clone();
+        node.link_state = entry.link_state;
+        node.ping_sent = entry.ping_sent;
+        node.pong_recv = entry.pong_recv;
+        node.config_epoch = entry.config_epoch;
+        Ok(node)
+    }
+
+    // conn open the connection on first use, nodes without address never connect.
+    fn conn(&self) -> AsResult<MutexGuard<'_, Option<Connection>>> {
+        if self.ip.is_empty() {
+            return Err(Error::Precondition(format!(
+                "node {} has no address",
+                self.name
+            )));
+        }
+        let mut guard = self.conn.lock().unwrap();
+        if guard.is_none() {
+            let opts = conn::opts();
+            let mut con = opts
+                .client(&self.ip, &self.port)
+                .and_then(|client| client.get_connection())
+                .map_err(|err| Error::Conn(self.addr(), err))?;
+            if let Some(auth) = opts.auth_args() {
+                let _: () = redis::cmd("AUTH")
+                    .arg(auth)
+                    .query(&mut con)
+                    .map_err(|err| Error::from_redis(self.addr(), err))?;
+            }
+            *guard = Some(con);
+        }
+        Ok(guard)
+    }
+
+    fn query<T: FromRedisValue>(&self, cmd: &Cmd) -> AsResult<T> {
+        let mut conn = self.conn()?;
+        cmd.query(conn.as_mut().unwrap())
+            .map_err(|err| Error::from_redis(self.addr(), err))
+    }
+
+    fn query_pipe<T: FromRedisValue>(&self, pipe: &Pipeline) -> AsResult<T> {
+        let mut conn = self.conn()?;
+        pipe.query(conn.as_mut().unwrap())
+            .map_err(|err| Error::from_redis(self.addr(), err))
+    }
+
+    pub fn connect(&mut self) -> AsResult<()> {
+        let nodes = self.nodes()?;
         for node in &nodes {
             if let Some(_t) = node.myself {
                 self.name = node.name.clone();
@@ -284,157 +743,196 @@ impl Node {
                 self.slots = node.slots.clone();
             }
         }
+        Ok(())
     }
 
-    pub fn fix_node(&self) {
+    pub fn fix_node(&self) -> AsResult<()> {
         for (slot, nodeid) in &self.migrating {
-            let target = self.nodes.borrow().get(nodeid).cloned().unwrap();
+            let target = self.view_node(nodeid)?;
+            if !target.is_available() {
+                println!(
+                    "skip slot {} migrating to unavailable node {}",
+                    slot,
+                    target.addr()
+                );
+                continue;
+            }
             if target.importing.contains_key(slot) {
-                migrate_slot(self, &target, *slot);
+                migrate_slot(self, &target, *slot)?;
                 continue;
             }
-            self.setslot_stable(*slot);
+            self.setslot_stable(*slot)?;
         }
         for (slot, nodeid) in &self.importing {
-            let target = self.nodes.borrow().get(nodeid).cloned().unwrap();
+            let target = self.view_node(nodeid)?;
+            if !target.is_available() {
+                println!(
+                    "skip slot {} importing from unavailable node {}",
+                    slot,
+                    target.addr()
+                );
+                continue;
+            }
             if target.importing.contains_key(slot) {
-                migrate_slot(self, &target, *slot);
+                migrate_slot(self, &target, *slot)?;
                 continue;
             }
-            self.setslot_stable(*slot);
+            self.setslot_stable(*slot)?;
         }
+        Ok(())
     }
 
-    pub fn info(&self) -> HashMap<String, String> {
-        let mut node_infos = HashMap::new();
-        let a = self.conn.as_ref().as_ref().unwrap();
-        let info: String = redis::cmd("CLUSTER").arg("INFO").query(a).unwrap();
-        let infos: Vec<String> = info.split("\r\n").map(|x| x.to_string()).collect();
+    // view_node return the node with id as seen by this node.
+    fn view_node(&self, nodeid: &str) -> AsResult<Node> {
+        if self.nodes.borrow().is_empty() {
+            self.nodes()?;
+        }
+        self.nodes.borrow().get(nodeid).cloned().ok_or_else(|| {
+            Error::Precondition(format!("node {} unknown to {}", nodeid, self.addr()))
+        })
+    }
 
-        for mut info in infos.into_iter() {
-            let kv: Vec<String> = info.split(':').map(|x| x.to_string()).collect();
-            if kv.len() == 2 {
-                node_infos.insert(kv[0].clone(), kv[1].clone());
-            }
+    pub fn info(&self) -> AsResult<HashMap<String, String>> {
+        let info: String = self.query(redis::cmd("CLUSTER").arg("INFO"))?;
+        Ok(parse_info(&info))
+    }
+
+    // server_info return all sections of the INFO command.
+    pub fn server_info(&self) -> AsResult<HashMap<String, String>> {
+        let info: String = self.query(&redis::cmd("INFO"))?;
+        Ok(parse_info(&info))
+    }
+
+    pub fn dbsize(&self) -> AsResult<u64> {
+        self.query(&redis::cmd("DBSIZE"))
+    }
+
+    // config_get return the value of a config parameter, none when unknown.
+    pub fn config_get(&self, name: &str) -> AsResult<Option<String>> {
+        let reply: Vec<String> = self.query(redis::cmd("CONFIG").arg("GET").arg(name))?;
+        Ok(reply.into_iter().nth(1))
+    }
+
+    // stats return the stats section of the INFO command.
+    pub fn stats(&self) -> AsResult<HashMap<String, String>> {
+        let info: String = self.query(redis::cmd("INFO").arg("stats"))?;
+        Ok(parse_info(&info))
+    }
+
+    // ping return the round trip time of a PING.
+    pub fn ping(&self) -> AsResult<Duration> {
+        let mut conn = self.conn()?;
+        let start = Instant::now();
+        let _: String = redis::cmd("PING")
+            .query(conn.as_mut().unwrap())
+            .map_err(|err| Error::from_redis(self.addr(), err))?;
+        Ok(start.elapsed())
+    }
+
+    // memory_usage return the MEMORY USAGE of each key, keys gone meanwhile count as 0.
+    pub fn memory_usage(&self, keys: &[String]) -> AsResult<Vec<u64>> {
+        let mut pipe = redis::pipe();
+        for key in keys {
+            pipe.cmd("MEMORY").arg("USAGE").arg(key);
         }
-        node_infos
+        let usage: Vec<Option<u64>> = self.query_pipe(&pipe)?;
+        Ok(usage.into_iter().map(|x| x.unwrap_or(0)).collect())
+    }
+
+    // key_state return the type, PTTL and MEMORY USAGE of key.
+    pub fn key_state(&self, key: &str) -> AsResult<KeyState> {
+        let mut pipe = redis::pipe();
+        pipe.cmd("TYPE")
+            .arg(key)
+            .cmd("PTTL")
+            .arg(key)
+            .cmd("MEMORY")
+            .arg("USAGE")
+            .arg(key);
+        let (kind, ttl, memory): (String, i64, Option<u64>) = self.query_pipe(&pipe)?;
+        if kind == "none" {
+            return Ok(KeyState::Missing);
+        }
+        Ok(KeyState::Found {
+            kind,
+            ttl,
+            memory: memory.unwrap_or(0),
+        })
     }
 
     pub fn set_role(&mut self, role: Role) {
         self.role = Some(role);
     }
 
-    pub fn set_slave(&self) {
-        let node_id = self.slaveof.clone().unwrap();
+    pub fn set_slave(&self) -> AsResult<()> {
+        let node_id = self.slaveof.clone().ok_or_else(|| {
+            Error::Precondition(format!("no master set for slave {}", self.addr()))
+        })?;
         println!("set {}  replicate to {}", self.ip, node_id);
-        if let Some(conn) = self.conn.as_ref() {
-            let _: () = redis::cmd("CLUSTER")
-                .arg("REPLICATE")
-                .arg(&*node_id)
-                .query(conn)
-                .expect("cluster replicate err");
-        }
+        self.query(redis::cmd("CLUSTER").arg("REPLICATE").arg(&*node_id))
     }
 
     pub fn addr(&self) -> String {
-        self.ip.clone() + ":" + &*self.port
+        topology::join_addr(&self.ip, &self.port)
     }
 
-    pub fn add_slots(&self, slots: &[usize]) {
-        if let Some(conn) = self.conn.as_ref() {
-            let _: () = redis::cmd("cluster")
-                .arg("addslots")
-                .arg(slots)
-                .query(conn)
-                .expect("add slots err");
-        }
+    pub fn add_slots(&self, slots: &[usize]) -> AsResult<()> {
+        self.query(redis::cmd("cluster").arg("addslots").arg(slots))
     }
 
-    pub fn set_config_epoch(&self, epoch: usize) {
-        if let Some(conn) = self.conn.as_ref() {
-            let _: () = redis::cmd("CLUSTER")
-                .arg("SET-CONFIG-EPOCH")
-                .arg(epoch)
-                .query(conn)
-                .expect("set config epoch err");
-        }
+    pub fn set_config_epoch(&self, epoch: usize) -> AsResult<()> {
+        self.query(redis::cmd("CLUSTER").arg("SET-CONFIG-EPOCH").arg(epoch))
     }
 
-    pub fn nodes(&self) -> Vec<Node> {
-        if let Some(conn) = self.conn.as_ref() {
-            let info: String = redis::cmd("CLUSTER").arg("NODES").query(conn).unwrap();
-            // let infos: Vec<String> = info.split("\n").map(|x| x.to_string()).collect();
-            let mut nodes: Vec<Node> = Vec::new();
-            for mut info in info.lines() {
-                let kv: Vec<String> = info.split(' ').map(|x| x.to_string()).collect();
-                if kv.len() < 8 {
-                    return vec![];
-                }
-                let mut slots = vec![];
-                let mut migrating = HashMap::new();
-                let mut importing = HashMap::new();
-                let addr = kv[1].split('@').next().expect("must contain addr");
-                let mut node = Node::new(addr.as_bytes()).unwrap();
-                if kv[2].contains("master") {
-                    node.set_role(Role::Master);
-                } else {
-                    node.set_role(Role::Slave);
-                }
-                if kv[2].contains("self") {
-                    node.myself = Some(true);
-                }
-                if kv[3] != "-" {
-                    node.slaveof = Some(kv[3].clone());
-                }
-                for content in &kv[8..] {
-                    if content.contains("->-") {
-                        let migrate = &content[1..content.len() - 1];
-                        let mut scope: Vec<&str> = migrate.split("->-").collect();
-                        let slot = scope[0].to_string().parse::<usize>().unwrap();
-                        let nodeid = scope[1];
-                        migrating.insert(slot, nodeid.to_string());
-                    } else if content.contains("-<-") {
-                        // trim [ ]
-                        let migrate = &content[1..content.len() - 1];
-                        let mut scope: Vec<&str> = migrate.split("-<-").collect();
-                        let slot = scope[0].to_string().parse::<usize>().unwrap();
-                        let nodeid = scope[1];
-                        importing.insert(slot, nodeid.to_string());
-                    } else {
-                        let mut scope: Vec<&str> = content.split('-').collect();
-                        let start = scope[0].to_string().parse::<usize>().unwrap();
-                        slots.push(start);
-                        if scope.len() == 2 {
-                            let end = scope[1].to_string().parse::<usize>().unwrap();
-                            for i in start + 1..=end {
-                                slots.push(i);
-                            }
-                        }
-                    }
-                }
-                node.migrating = migrating;
-                node.importing = importing;
-                node.slots = RefCell::new(slots);
-                node.name = kv[0].clone();
-                self.nodes
-                    .borrow_mut()
-                    .insert(node.name.clone(), node.clone());
-                nodes.push(node);
-            }
-            return nodes;
+    // bump_epoch give the node a new config epoch above all others, false
+    // when it already has the highest one.
+    pub fn bump_epoch(&self) -> AsResult<bool> {
+        let reply: String = self.query(redis::cmd("CLUSTER").arg("BUMPEPOCH"))?;
+        Ok(reply.starts_with("BUMPED"))
+    }
+
+    // failover start a manual failover of the replica, its master is demoted
+    // once the replica caught up.
+    pub fn failover(&self) -> AsResult<()> {
+        self.query(redis::cmd("CLUSTER").arg("FAILOVER"))
+    }
+
+    // topology return the cluster as seen by this node.
+    pub fn topology(&self) -> AsResult<Topology> {
+        let info: String = self.query(redis::cmd("CLUSTER").arg("NODES"))?;
+        Topology::parse(&info)
+    }
+
+    pub fn nodes(&self) -> AsResult<Vec<Node>> {
+        let topology = self.topology()?;
+        let mut nodes: Vec<Node> = Vec::new();
+        for entry in &topology.nodes {
+            let node = Node::from_entry(entry)?;
+            self.nodes
+                .borrow_mut()
+                .insert(node.name.clone(), node.clone());
+            nodes.push(node);
         }
-        vec![]
+        Ok(nodes)
     }
 
-    pub fn meet(&self, ip: &str, port: &str) {
-        if let Some(conn) = self.conn.as_ref() {
-            let _: () = redis::cmd("CLUSTER")
+    // meet accept a name for ip, CLUSTER MEET only take an IP so it is resolved first.
+    pub fn meet(&self, ip: &str, port: &str) -> AsResult<()> {
+        let ip = match ip.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => (ip, port.parse::<u16>().map_err(|_| Error::BadAddr)?)
+                .to_socket_addrs()
+                .map_err(|err| Error::Io(ip.to_string(), err))?
+                .next()
+                .ok_or(Error::BadAddr)?
+                .ip(),
+        };
+        self.query(
+            redis::cmd("CLUSTER")
                 .arg("MEET")
-                .arg(ip)
-                .arg(port)
-                .query(conn)
-                .unwrap();
-        }
+                .arg(ip.to_string())
+                .arg(port),
+        )
     }
 
     pub fn slots(&self) -> Vec<usize> {
@@ -445,74 +943,151 @@ impl Node {
         self.role == Some(Role::Master)
     }
 
-    pub fn forget(&self, node: &Node) {
-        if let Some(conn) = self.conn.as_ref() {
-            let _: () = redis::cmd("CLUSTER")
-                .arg("FORGET")
-                .arg(&node.name)
-                .query(conn)
-                .unwrap();
-        }
+    // is_available report whether the node is neither failed, handshaking nor
+    // without address, commands to an unavailable node are skipped.
+    pub fn is_available(&self) -> bool {
+        self.flags.is_available()
+    }
+
+    pub fn forget(&self, node: &Node) -> AsResult<()> {
+        self.query(redis::cmd("CLUSTER").arg("FORGET").arg(&node.name))
     }
 
-    pub fn setslot(&self, state: &str, nodeid: String, slot: usize) {
-        if let Some(conn) = self.conn.as_ref() {
-            let _: () = redis::cmd("CLUSTER")
+    pub fn setslot(&self, state: &str, nodeid: String, slot: usize) -> AsResult<()> {
+        self.query(
+            redis::cmd("CLUSTER")
                 .arg("SETSLOT")
                 .arg(slot)
                 .arg(state)
-                .arg(&*nodeid)
-                .query(conn)
-                .unwrap();
-        }
+                .arg(&*nodeid),
+        )
     }
-    fn setslot_stable(&self, slot: usize) {
-        if let Some(conn) = self.conn.as_ref() {
-            let _: () = redis::cmd("CLUSTER")
-                .arg("SETSLOT")
-                .arg(slot)
-                .arg("STABLE")
-                .query(conn)
-                .unwrap();
-        }
+
+    pub fn setslot_stable(&self, slot: usize) -> AsResult<()> {
+        self.query(redis::cmd("CLUSTER").arg("SETSLOT").arg(slot).arg("STABLE"))
     }
 
-    fn keysinslot(&self, slot: usize) -> Option<Vec<String>> {
-        if let Some(conn) = self.conn.as_ref() {
-            let result: Vec<String> = redis::cmd("CLUSTER")
+    pub fn keysinslot(&self, slot: usize, count: usize) -> AsResult<Vec<String>> {
+        self.query(
+            redis::cmd("CLUSTER")
                 .arg("GETKEYSINSLOT")
                 .arg(slot)
-                .arg(100)
-                .query(conn)
-                .unwrap();
-            if result.is_empty() {
-                return None;
-            }
-            return Some(result);
+                .arg(count),
+        )
+    }
+
+    pub fn countkeysinslot(&self, slot: usize) -> AsResult<usize> {
+        self.query(redis::cmd("CLUSTER").arg("COUNTKEYSINSLOT").arg(slot))
+    }
+
+    // countkeysinslots count the keys of many slots in one round trip.
+    pub fn countkeysinslots(&self, slots: &[usize]) -> AsResult<Vec<usize>> {
+        let mut pipe = redis::pipe();
+        for slot in slots {
+            pipe.cmd("CLUSTER").arg("COUNTKEYSINSLOT").arg(*slot);
         }
-        None
+        self.query_pipe(&pipe)
+    }
+
+    fn migrate(&self, dst: &Node, keys: &[String], opts: &MigrateOpts) -> AsResult<()> {
+        // the destination need the same credentials as we do.
+        let args = migrate::migrate_args(&dst.ip, &dst.port, keys, opts, conn::opts().auth_args());
+        self.query(redis::cmd("MIGRATE").arg(args))
     }
 
-    fn migrate(&self, dstip: &str, dstport: &str, key: Vec<String>) {
-        if let Some(conn) = self.conn.as_ref() {
-            let _: () = redis::cmd("MIGRATE")
-                .arg(dstip)
-                .arg(dstport)
-                .arg("")
-                .arg("0")
-                .arg(5000)
-                .arg("KEYS")
-                .arg(key)
-                .query(conn)
-                .unwrap();
+    // exists tell which keys are on the node, ASKING let it answer for a slot
+    // it is importing.
+    fn exists(&self, keys: &[String]) -> AsResult<Vec<bool>> {
+        let mut pipe = redis::pipe();
+        for key in keys {
+            pipe.cmd("ASKING").ignore().cmd("EXISTS").arg(key);
         }
+        self.query_pipe(&pipe)
     }
+
+    // asking run cmd on a key of a slot the node is importing.
+    fn asking<T: FromRedisValue>(&self, cmd: &Cmd) -> AsResult<T> {
+        let mut pipe = redis::pipe();
+        pipe.cmd("ASKING").ignore().add_command(cmd.clone());
+        let (reply,): (T,) = self.query_pipe(&pipe)?;
+        Ok(reply)
+    }
+
+    // unlink remove keys of a slot the node is importing and free them in the
+    // background.
+    fn unlink(&self, keys: &[String]) -> AsResult<()> {
+        self.asking(redis::cmd("UNLINK").arg(keys))
+    }
+}
+
+fn parse_info(info: &str) -> HashMap<String, String> {
+    let mut node_infos = HashMap::new();
+    for line in info.split("\r\n") {
+        let kv: Vec<&str> = line.splitn(2, ':').collect();
+        if kv.len() == 2 {
+            node_infos.insert(kv[0].to_string(), kv[1].to_string());
+        }
+    }
+    node_infos
 }
 
 #[derive(Debug)]
 pub enum Error {
     BadAddr,
     BadCluster,
+    InvalidArg(String),
+    Parse(String),
+    // Conn is a failure to reach the node at the address.
+    Conn(String, RedisError),
+    // Redis is an error reply, like MOVED or BUSYKEY, from the node at the address.
+    Redis(String, RedisError),
+    Timeout(String),
+    Io(String, io::Error),
+    // Precondition is raised when the cluster is not in the state an operation expects.
+    Precondition(String),
+}
+
+impl Error {
+    fn from_redis(addr: String, err: RedisError) -> Error {
+        if err.is_timeout() {
+            Error::Timeout(addr)
+        } else if err.is_io_error() {
+            Error::Conn(addr, err)
+        } else {
+            Error::Redis(addr, err)
+        }
+    }
+
+    // exit_code is the process exit code the cli report the error with.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io(..) => 1,
+            Error::BadAddr | Error::InvalidArg(_) => 2,
+            Error::Conn(..) => 3,
+            Error::Timeout(_) => 4,
+            Error::Redis(..) => 5,
+            Error::Parse(_) => 6,
+            Error::BadCluster | Error::Precondition(_) => 7,
+        }
+    }
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BadAddr => write!(f, "bad address"),
+            Error::BadCluster => write!(f, "bad cluster"),
+            Error::InvalidArg(msg) => write!(f, "invalid argument: {}", msg),
+            Error::Parse(msg) => write!(f, "parse error: {}", msg),
+            Error::Conn(addr, err) => write!(f, "connect to {} fail: {}", addr, err),
+            Error::Redis(addr, err) => write!(f, "redis error from {}: {}", addr, err),
+            Error::Timeout(addr) => write!(f, "timeout on {}", addr),
+            Error::Io(path, err) => write!(f, "{}: {}", path, err),
+            Error::Precondition(msg) => write!(f, "precondition fail: {}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
 pub type AsResult<T> = result::Result<T, Error>;
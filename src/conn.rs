@@ -0,0 +1,222 @@
+use cluster::{AsResult, Error};
+use redis::{Client, ClientTlsConfig, ErrorKind, RedisError, RedisResult, TlsCertificates};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::RwLock;
+use topology::join_addr;
+
+#[test]
+fn test_parse_credentials() {
+    let opts = ConnOpts::parse_credentials("# rckit\nuser = admin\npassword=p@ss=word\n").unwrap();
+    assert_eq!(opts.username, Some("admin".to_string()));
+    assert_eq!(opts.password, Some("p@ss=word".to_string()));
+    assert!(ConnOpts::parse_credentials("token=abc").is_err());
+    assert!(ConnOpts::parse_credentials("password").is_err());
+}
+
+#[test]
+fn test_auth_args() {
+    let mut opts = ConnOpts::default();
+    assert!(opts.auth_args().is_none());
+    opts.username = Some("admin".to_string());
+    assert!(opts.auth_args().is_none());
+    opts.password = Some("secret".to_string());
+    assert_eq!(
+        opts.auth_args(),
+        Some(vec!["admin".to_string(), "secret".to_string()])
+    );
+    opts.username = None;
+    assert_eq!(opts.auth_args(), Some(vec!["secret".to_string()]));
+}
+
+#[test]
+fn test_url() {
+    let mut opts = ConnOpts::default();
+    assert_eq!(opts.url("127.0.0.1", "7000"), "redis://127.0.0.1:7000");
+    assert_eq!(opts.url("::1", "7000"), "redis://[::1]:7000");
+    opts.tls = Some(TlsOpts::default());
+    assert_eq!(opts.url("127.0.0.1", "7000"), "rediss://127.0.0.1:7000");
+    opts.tls = Some(TlsOpts {
+        insecure: true,
+        ..TlsOpts::default()
+    });
+    assert_eq!(
+        opts.url("127.0.0.1", "7000"),
+        "rediss://127.0.0.1:7000/#insecure"
+    );
+}
+
+#[test]
+fn test_tls_certificates() {
+    let tls = TlsOpts {
+        cert_file: Some("client.crt".to_string()),
+        ..TlsOpts::default()
+    };
+    assert!(tls.certificates().is_err());
+    let tls = TlsOpts {
+        ca_file: Some("/nonexistent/ca.crt".to_string()),
+        ..TlsOpts::default()
+    };
+    assert!(tls.certificates().is_err());
+    assert!(TlsOpts::default().certificates().unwrap().is_none());
+}
+
+pub const ENV_USER: &str = "RCKIT_USER";
+pub const ENV_PASSWORD: &str = "RCKIT_PASSWORD";
+
+// ConnOpts is applied to every connection made to a node.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnOpts {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // tls is set when nodes only accept TLS connections.
+    pub tls: Option<TlsOpts>,
+}
+
+// TlsOpts hold PEM files, the system trust store is used without ca_file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsOpts {
+    pub ca_file: Option<String>,
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    // insecure skip the verification of the server certificate, for labs only.
+    pub insecure: bool,
+}
+
+static OPTS: RwLock<ConnOpts> = RwLock::new(ConnOpts {
+    username: None,
+    password: None,
+    tls: None,
+});
+
+// set_opts replace the options used by connections opened afterward.
+pub fn set_opts(opts: ConnOpts) {
+    *OPTS.write().unwrap() = opts;
+}
+
+pub fn opts() -> ConnOpts {
+    OPTS.read().unwrap().clone()
+}
+
+impl ConnOpts {
+    // resolve pick every option from the flag first, then the environment,
+    // then the credentials file.
+    pub fn resolve(
+        username: Option<&str>,
+        password: Option<&str>,
+        file: Option<&str>,
+    ) -> AsResult<ConnOpts> {
+        let from_file = match file {
+            Some(path) => {
+                let text =
+                    fs::read_to_string(path).map_err(|err| Error::Io(path.to_string(), err))?;
+                ConnOpts::parse_credentials(&text)?
+            }
+            None => ConnOpts::default(),
+        };
+        let pick = |flag: Option<&str>, key: &str, file: Option<String>| {
+            flag.map(|x| x.to_string())
+                .or_else(|| env::var(key).ok())
+                .or(file)
+        };
+        Ok(ConnOpts {
+            username: pick(username, ENV_USER, from_file.username),
+            password: pick(password, ENV_PASSWORD, from_file.password),
+            tls: None,
+        })
+    }
+
+    // parse_credentials read "user=<name>" and "password=<secret>" lines,
+    // blank lines and lines start with # are ignored.
+    pub fn parse_credentials(text: &str) -> AsResult<ConnOpts> {
+        let mut kv = HashMap::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let item: Vec<&str> = line.splitn(2, '=').collect();
+            if item.len() != 2 {
+                return Err(Error::Parse(format!("bad credentials line '{}'", line)));
+            }
+            match item[0].trim() {
+                key @ "user" | key @ "password" => {
+                    kv.insert(key, item[1].trim().to_string());
+                }
+                key => return Err(Error::Parse(format!("unknown credentials key {}", key))),
+            }
+        }
+        Ok(ConnOpts {
+            username: kv.remove("user"),
+            password: kv.remove("password"),
+            tls: None,
+        })
+    }
+
+    pub fn url(&self, ip: &str, port: &str) -> String {
+        match &self.tls {
+            Some(tls) if tls.insecure => format!("rediss://{}/#insecure", join_addr(ip, port)),
+            Some(_) => format!("rediss://{}", join_addr(ip, port)),
+            None => format!("redis://{}", join_addr(ip, port)),
+        }
+    }
+
+    pub fn client(&self, ip: &str, port: &str) -> RedisResult<Client> {
+        let url = self.url(ip, port);
+        match self.tls.as_ref().map(|tls| tls.certificates()) {
+            Some(Err(err)) => Err(err),
+            Some(Ok(Some(certs))) => Client::build_with_tls(url, certs),
+            _ => Client::open(url),
+        }
+    }
+
+    // auth_args is the argument of AUTH, [password] or [username, password]
+    // for an ACL user. None when no password is set.
+    pub fn auth_args(&self) -> Option<Vec<String>> {
+        let password = self.password.clone()?;
+        Some(match &self.username {
+            Some(username) => vec![username.clone(), password],
+            None => vec![password],
+        })
+    }
+}
+
+impl TlsOpts {
+    // certificates load the PEM files, None when only the system trust store is needed.
+    pub fn certificates(&self) -> RedisResult<Option<TlsCertificates>> {
+        let read = |path: &str| {
+            fs::read(path).map_err(|err| {
+                RedisError::from((
+                    ErrorKind::InvalidClientConfig,
+                    "read tls file fail",
+                    format!("{}: {}", path, err),
+                ))
+            })
+        };
+        let client_tls = match (&self.cert_file, &self.key_file) {
+            (Some(cert), Some(key)) => Some(ClientTlsConfig {
+                client_cert: read(cert)?,
+                client_key: read(key)?,
+            }),
+            (None, None) => None,
+            _ => {
+                return Err(RedisError::from((
+                    ErrorKind::InvalidClientConfig,
+                    "tls client certificate and key must be given together",
+                )))
+            }
+        };
+        let root_cert = match &self.ca_file {
+            Some(ca) => Some(read(ca)?),
+            None => None,
+        };
+        if client_tls.is_none() && root_cert.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(TlsCertificates {
+            client_tls,
+            root_cert,
+        }))
+    }
+}
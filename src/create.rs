@@ -1,8 +1,12 @@
 use cluster::{Cluster, Error, Node};
+use plan::{Plan, Step};
 use std::collections::HashMap;
+use std::hash::Hash;
 use util;
+use zones::Zones;
 
 #[test]
+#[allow(clippy::vec_init_then_push)]
 fn test_cluster() {
     let mut addrs: Vec<&str> = Vec::new();
     addrs.push("127.0.0.1:9999");
@@ -14,8 +18,8 @@ fn test_cluster() {
     addrs.push("127.0.0.3:8889");
     addrs.push("127.0.0.4:8889");
 
-    let mut cluster = Create::new(addrs, 4, 4).unwrap();
-    cluster.init_slots();
+    let mut cluster = Create::new(addrs, Zones::default(), 4, 4).unwrap();
+    cluster.init_slots().unwrap();
     assert_eq!(cluster.master.len(), 4);
     assert_eq!(cluster.slots.len(), 4);
     assert_eq!(cluster.slave.len(), 4);
@@ -38,10 +42,10 @@ fn test_split_slots() {
 #[test]
 fn test_spread() {
     let mut map = HashMap::new();
-    let node1 = Node::new(b"aa::bb").unwrap();
-    let node2 = Node::new(b"bb::bb").unwrap();
-    let node3 = Node::new(b"cc::bb").unwrap();
-    let node4 = Node::new(b"dd:bb").unwrap();
+    let node1 = Node::new(b"aa:7000").unwrap();
+    let node2 = Node::new(b"bb:7000").unwrap();
+    let node3 = Node::new(b"cc:7000").unwrap();
+    let node4 = Node::new(b"dd:7000").unwrap();
     map.insert("11", vec![node1, node2]);
     map.insert("13", vec![node3, node4]);
     let mut target = spread(&mut map, 3).unwrap();
@@ -51,6 +55,45 @@ fn test_spread() {
     println!("{:?}", target.pop());
 }
 
+#[test]
+fn test_place_replicas() {
+    let placement = place_replicas(&["a", "b", "c"], &["a", "b", "c"]);
+    assert!(placement.colocated.is_empty());
+    let mut masters = placement.masters.clone();
+    masters.sort();
+    assert_eq!(masters, vec![0, 1, 2]);
+    for (replica, master) in placement.masters.iter().enumerate() {
+        assert_ne!(replica, *master);
+    }
+
+    // two replicas per master, never on the host of the master.
+    let masters = ["a", "b", "c"];
+    let replicas = ["a", "a", "b", "b", "c", "c"];
+    let placement = place_replicas(&masters, &replicas);
+    assert!(placement.colocated.is_empty());
+    for (master, host) in masters.iter().enumerate() {
+        let hosts: Vec<&str> = placement
+            .masters
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| **m == master)
+            .map(|(r, _)| replicas[r])
+            .collect();
+        assert_eq!(hosts.len(), 2);
+        assert!(!hosts.contains(host));
+    }
+
+    // safety comes before evenness.
+    let placement = place_replicas(&["a", "b"], &["b", "b"]);
+    assert_eq!(placement.masters, vec![0, 0]);
+    assert!(placement.colocated.is_empty());
+
+    // a single host can't be safe, replicas are still spread evenly.
+    let placement = place_replicas(&["a", "a", "a"], &["a", "a", "a"]);
+    assert_eq!(placement.masters, vec![0, 1, 2]);
+    assert_eq!(placement.colocated, vec![0, 1, 2]);
+}
+
 pub struct Create {
     pub cluster: Cluster,
     master_count: usize,
@@ -58,21 +101,24 @@ pub struct Create {
     slots: Vec<Chunk>,
     master: Vec<Node>,
     slave: Vec<Node>,
+    // zones label the failure domain of the nodes, unlabelled nodes are
+    // grouped by host.
+    zones: Zones,
 }
 
 const CLUSTER_SLOTS: usize = 16384;
 
 impl Create {
-
     pub fn new(
         addrs: Vec<&str>,
+        zones: Zones,
         mut master_count: usize,
         slave_count: usize,
     ) -> Result<Create, Error> {
         let mut nodes = Vec::new();
         for n in addrs.into_iter() {
-            let mut node = Node::new(n.as_bytes()).unwrap();
-            node.connect();
+            let mut node = Node::new(n.as_bytes())?;
+            node.connect()?;
             nodes.push(node);
         }
         let mut create = Create {
@@ -82,6 +128,7 @@ impl Create {
             slots: vec![],
             master: vec![],
             slave: vec![],
+            zones,
         };
         if master_count == 0 {
             master_count = create.cluster.len() / (slave_count + 1);
@@ -95,88 +142,197 @@ impl Create {
         }
     }
 
-    pub fn init_slots(&mut self) {
+    pub fn init_slots(&mut self) -> Result<(), Error> {
         let slaves = {
-            let mut ips = HashMap::new();
+            let mut domains = HashMap::new();
             for n in &self.cluster.nodes {
-                let key = &*n.ip;
-                ips.entry(key).or_insert_with(|| vec![]).push(n.clone());
-            }
-            self.master = spread(&mut ips, self.master_count).expect("spread master err");
-            println!("create redis cluster");
-            println!("distribute master");
-            for node in &self.master {
-                println!("master: {:?}", node);
+                domains
+                    .entry(self.zones.domain(&n.ip, &n.port))
+                    .or_insert_with(Vec::new)
+                    .push(n.clone());
             }
+            self.master = spread(&mut domains, self.master_count).ok_or_else(|| {
+                Error::Precondition(format!(
+                    "{} nodes is not enough for {} masters",
+                    self.cluster.len(),
+                    self.master_count
+                ))
+            })?;
             self.slots = slpit_slots(CLUSTER_SLOTS, self.master_count).unwrap();
-            spread(&mut ips, self.cluster.len() - self.master_count).unwrap()
+            spread(&mut domains, self.cluster.len() - self.master_count).unwrap()
         };
         self.distribute_slave(slaves);
-        println!("distributie slave");
-        for node in &self.slave {
-            println!("slave: {:?}", node);
-        }
-    }
-
-    pub fn add_slots(&mut self) {
-        for node in &self.master {
-            let chunk = &self.slots.pop().unwrap();
-            node.add_slots(&(chunk.0..chunk.1).collect::<Vec<usize>>());
-        }
+        Ok(())
     }
 
-    pub fn set_config_epoch(&self) {
-        let epoch = 1;
-        for node in &self.master {
-            node.set_config_epoch(epoch);
+    // plan assign the slots to the masters, join all nodes together then set
+    // the slaves once the cluster is consistent.
+    pub fn plan(&self) -> Plan {
+        let mut plan = Plan::default();
+        for (node, chunk) in self.master.iter().zip(self.slots.iter().rev()) {
+            plan.push(Step::AddSlots {
+                node: node.addr(),
+                slots: vec![(chunk.0, chunk.1 - 1)],
+            });
         }
-    }
-
-    pub fn join_cluster(&mut self) {
-        if self.cluster.len() == 0 {
-            return;
+        // every master start with its own epoch so no collision has to be
+        // resolved by the cluster.
+        for (idx, node) in self.master.iter().enumerate() {
+            plan.push(Step::SetConfigEpoch {
+                node: node.addr(),
+                epoch: idx as u64 + 1,
+            });
         }
-        let first_node = self.cluster.nodes.pop().unwrap();
-        for node in &self.cluster.nodes {
-            first_node.meet(&*node.ip, &*node.port);
+        if let Some((first_node, others)) = self.cluster.nodes.split_last() {
+            for node in others {
+                plan.push(Step::Meet {
+                    node: first_node.addr(),
+                    peer: node.addr(),
+                });
+            }
+            plan.push(Step::WaitConsistent {
+                nodes: others.iter().map(|n| n.addr()).collect(),
+            });
         }
-    }
-
-    pub fn set_slave(&self) -> Result<(), Error> {
         for node in &self.slave {
-            let _: () = node.set_slave();
+            let master = self
+                .master
+                .iter()
+                .find(|m| Some(&m.name) == node.slaveof.as_ref());
+            if let Some(master) = master {
+                plan.push(Step::Replicate {
+                    node: node.addr(),
+                    master: master.addr(),
+                });
+            }
         }
-        Ok(())
+        plan
     }
 
     fn distribute_slave(&mut self, slaves: Vec<Node>) {
-        let mut inuse = HashMap::new();
-        loop {
-            for master in &self.master {
-                for slave in &slaves {
-                    // if master.ip == slave.ip {
-                    //     continue;
-                    // }
-                    let mut key = slave.ip.clone() + ":" + &slave.port;
-                    if inuse.contains_key(&key) {
-                        continue;
-                    }
-                    inuse.insert(key, slave);
-                    let mut s =
-                        Node::new((slave.ip.clone() + ":" + &*slave.port).as_bytes()).unwrap();
-                    s.slaveof = Some(master.name.clone());
-                    self.slave.push(s);
-                    break;
-                }
+        let domain = |n: &Node| self.zones.domain(&n.ip, &n.port);
+        let masters: Vec<String> = self.master.iter().map(domain).collect();
+        let domains: Vec<String> = slaves.iter().map(domain).collect();
+        let placement = place_replicas(
+            &masters.iter().map(|m| m.as_str()).collect::<Vec<_>>(),
+            &domains.iter().map(|d| d.as_str()).collect::<Vec<_>>(),
+        );
+        for (idx, (slave, master)) in slaves.iter().zip(&placement.masters).enumerate() {
+            let master = &self.master[*master];
+            if placement.colocated.contains(&idx) {
+                eprintln!(
+                    "{}",
+                    colocated_warning(&slave.addr(), &domains[idx], &master.addr(), DOMAIN_HINT)
+                );
             }
-            if self.slave.len() >= slaves.len() {
-                break;
+            let mut s = slave.clone();
+            s.slaveof = Some(master.name.clone());
+            self.slave.push(s);
+        }
+    }
+}
+
+// Placement is the master index of every replica, colocated list the
+// replicas that had to share the failure domain of their master.
+#[derive(Debug, PartialEq)]
+pub struct Placement {
+    pub masters: Vec<usize>,
+    pub colocated: Vec<usize>,
+}
+
+pub const DOMAIN_HINT: &str = "add nodes in other failure domains to avoid it";
+
+// colocated_warning tell that a replica ended up in the failure domain of its
+// master, hint is what the user can do about it.
+pub fn colocated_warning(replica: &str, domain: &str, master: &str, hint: &str) -> String {
+    format!(
+        "warning: replica {} is in the same {} as its master {}, {}",
+        replica, domain, master, hint
+    )
+}
+
+// place_replicas assign replicas to masters in another failure domain, up to an
+// even share per master first, the replicas left over share their domain.
+pub fn place_replicas(masters: &[&str], replicas: &[&str]) -> Placement {
+    let mut placement = Placement {
+        masters: vec![],
+        colocated: vec![],
+    };
+    if masters.is_empty() {
+        return placement;
+    }
+    let mut assigned: Vec<Option<usize>> = vec![None; replicas.len()];
+    let mut members: Vec<Vec<usize>> = vec![vec![]; masters.len()];
+    let even = replicas.len() / masters.len();
+    let even_up = replicas.len().div_ceil(masters.len());
+    for cap in &[even, even_up, replicas.len()] {
+        for replica in 0..replicas.len() {
+            if assigned[replica].is_none() {
+                let mut seen = vec![false; masters.len()];
+                let mut matching = Matching {
+                    masters,
+                    replicas,
+                    cap: *cap,
+                    assigned: &mut assigned,
+                    members: &mut members,
+                    seen: &mut seen,
+                };
+                matching.augment(replica);
             }
         }
     }
+    let left: Vec<usize> = (0..replicas.len())
+        .filter(|replica| assigned[*replica].is_none())
+        .collect();
+    for replica in left {
+        let master = (0..masters.len())
+            .min_by_key(|m| (members[*m].len(), *m))
+            .unwrap();
+        members[master].push(replica);
+        assigned[replica] = Some(master);
+        placement.colocated.push(replica);
+    }
+    placement.masters = assigned.into_iter().map(|m| m.unwrap()).collect();
+    placement
+}
 
-    pub fn consistent(&self) -> bool {
-        self.cluster.consistency()
+struct Matching<'a> {
+    masters: &'a [&'a str],
+    replicas: &'a [&'a str],
+    cap: usize,
+    assigned: &'a mut Vec<Option<usize>>,
+    members: &'a mut Vec<Vec<usize>>,
+    seen: &'a mut Vec<bool>,
+}
+
+impl<'a> Matching<'a> {
+    // augment find a master in another domain for replica, moving the replicas
+    // already placed when needed. The least loaded masters are tried first.
+    fn augment(&mut self, replica: usize) -> bool {
+        let mut candidates: Vec<usize> = (0..self.masters.len())
+            .filter(|m| self.masters[*m] != self.replicas[replica])
+            .collect();
+        candidates.sort_by_key(|m| (self.members[*m].len(), *m));
+        for master in candidates {
+            if self.seen[master] {
+                continue;
+            }
+            self.seen[master] = true;
+            if self.members[master].len() < self.cap {
+                self.members[master].push(replica);
+                self.assigned[replica] = Some(master);
+                return true;
+            }
+            for other in self.members[master].clone() {
+                if self.augment(other) {
+                    self.members[master].retain(|r| *r != other);
+                    self.members[master].push(replica);
+                    self.assigned[replica] = Some(master);
+                    return true;
+                }
+            }
+        }
+        false
     }
 }
 
@@ -200,7 +356,7 @@ impl PartialEq for Chunk {
     }
 }
 
-fn spread(nodes: &mut HashMap<&str, Vec<Node>>, n: usize) -> Option<Vec<Node>> {
+fn spread<K: Eq + Hash>(nodes: &mut HashMap<K, Vec<Node>>, n: usize) -> Option<Vec<Node>> {
     let mut target: Vec<Node> = Vec::new();
     let len = {
         let mut len: usize = 0;
@@ -217,9 +373,9 @@ fn spread(nodes: &mut HashMap<&str, Vec<Node>>, n: usize) -> Option<Vec<Node>> {
             if target.len() >= n {
                 return Some(target);
             }
-            let node = v.pop().unwrap();
-            // println!("{:?} {} {}", &node, target.len(), n);
-            target.push(node);
+            if let Some(node) = v.pop() {
+                target.push(node);
+            }
         }
     }
     // let target = nodes
@@ -0,0 +1,243 @@
+use cluster::{self, AsResult, Error, Node};
+use plan;
+use serde_json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+#[test]
+fn test_parse_journal() {
+    let text = r#"{"event":"planned","src":"127.0.0.1:7000","dst":"127.0.0.1:7001","slot":1}
+{"event":"planned","src":"127.0.0.1:7000","dst":"127.0.0.1:7001","slot":2}
+{"event":"planned","src":"127.0.0.1:7000","dst":"127.0.0.1:7002","slot":3}
+{"event":"started","src":"127.0.0.1:7000","dst":"127.0.0.1:7001","slot":1}
+{"event":"done","src":"127.0.0.1:7000","dst":"127.0.0.1:7001","slot":1}
+{"event":"started","src":"127.0.0.1:7000","dst":"127.0.0.1:7001","slot":2}
+{"event":"done","src":"127.0.0.1:7000","dst":"127.0.0.1:7001","#;
+    let pending = parse(text).unwrap();
+    assert_eq!(pending.len(), 2);
+    assert_eq!(pending[0].slot, 2);
+    assert!(pending[0].started);
+    assert_eq!(pending[1].dst, "127.0.0.1:7002");
+    assert!(!pending[1].started);
+    assert!(parse("{}\n{}\n").is_err());
+    assert!(parse("").unwrap().is_empty());
+}
+
+#[test]
+fn test_journal_file() {
+    let path = temp_path("rckit-test-journal");
+    let _ = fs::remove_file(&path);
+    let moves = vec![("a:1".to_string(), "b:2".to_string(), 7)];
+    let journal = Journal::create(&path, &moves).unwrap();
+    assert!(!journal.is_started());
+    journal.record(Event::Started, "a:1", "b:2", 7).unwrap();
+    assert!(journal.is_started());
+    assert_eq!(pending(&path).unwrap().len(), 1);
+    assert!(Journal::create(&path, &moves).is_err());
+    journal.record(Event::Done, "a:1", "b:2", 7).unwrap();
+    assert!(pending(&path).unwrap().is_empty());
+    journal.finish().unwrap();
+    assert!(!path.exists());
+}
+
+#[cfg(test)]
+fn temp_path(name: &str) -> PathBuf {
+    ::std::env::temp_dir().join(format!("{}-{}", name, ::std::process::id()))
+}
+
+// DEFAULT_PATH is used when --journal is not given.
+pub const DEFAULT_PATH: &str = "rckit.journal";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Planned,
+    Started,
+    Done,
+}
+
+// Entry is one line of the journal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    event: Event,
+    src: String,
+    dst: String,
+    slot: usize,
+}
+
+// Pending is a planned slot move without done entry, started is set when the
+// move may have left the slot open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pending {
+    pub src: String,
+    pub dst: String,
+    pub slot: usize,
+    pub started: bool,
+}
+
+// Journal record the planned slot moves of a command and the progress of
+// each of them, one json object per line so a crash lose at most the last line.
+// It is shared by the workers of a parallel migration.
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+    started: AtomicBool,
+}
+
+impl Journal {
+    // create start a journal for moves, it refuse to overwrite a journal
+    // with unfinished moves.
+    pub fn create<P: AsRef<Path>>(path: P, moves: &[(String, String, usize)]) -> AsResult<Journal> {
+        let path = path.as_ref();
+        let left = pending(path)?;
+        if !left.is_empty() {
+            return Err(Error::Precondition(format!(
+                "journal {} has {} unfinished slot moves, run rckit resume first",
+                path.display(),
+                left.len()
+            )));
+        }
+        let file = File::create(path).map_err(|err| Error::Io(path.display().to_string(), err))?;
+        let journal = Journal {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            started: AtomicBool::new(false),
+        };
+        for (src, dst, slot) in moves {
+            journal.record(Event::Planned, src, dst, *slot)?;
+        }
+        Ok(journal)
+    }
+
+    // open append to an existing journal, used by resume.
+    pub fn open<P: AsRef<Path>>(path: P) -> AsResult<Journal> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|err| Error::Io(path.display().to_string(), err))?;
+        Ok(Journal {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            started: AtomicBool::new(true),
+        })
+    }
+
+    // is_started report whether a move may have changed the cluster.
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
+    // record write the entry through to the disk before returning.
+    pub fn record(&self, event: Event, src: &str, dst: &str, slot: usize) -> AsResult<()> {
+        if event == Event::Started {
+            self.started.store(true, Ordering::SeqCst);
+        }
+        let entry = Entry {
+            event,
+            src: src.to_string(),
+            dst: dst.to_string(),
+            slot,
+        };
+        let line = serde_json::to_string(&entry).map_err(|err| Error::Parse(err.to_string()))?;
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", line)
+            .and_then(|_| file.sync_data())
+            .map_err(|err| Error::Io(self.path.display().to_string(), err))
+    }
+
+    // finish remove the journal once all moves are done.
+    pub fn finish(self) -> AsResult<()> {
+        fs::remove_file(&self.path).map_err(|err| Error::Io(self.path.display().to_string(), err))
+    }
+}
+
+// pending return the unfinished moves of the journal at path, none if the
+// file does not exist.
+pub fn pending<P: AsRef<Path>>(path: P) -> AsResult<Vec<Pending>> {
+    let path = path.as_ref();
+    match fs::read_to_string(path) {
+        Ok(text) => parse(&text),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(Error::Io(path.display().to_string(), err)),
+    }
+}
+
+fn parse(text: &str) -> AsResult<Vec<Pending>> {
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    let mut moves: BTreeMap<(String, String, usize), (usize, bool)> = BTreeMap::new();
+    let mut planned = 0;
+    for (idx, line) in lines.iter().enumerate() {
+        let entry: Entry = match serde_json::from_str(line) {
+            Ok(entry) => entry,
+            // the last line is cut when rckit is killed while writing it.
+            Err(_) if idx + 1 == lines.len() => break,
+            Err(err) => return Err(Error::Parse(format!("journal line {}: {}", idx + 1, err))),
+        };
+        let key = (entry.src, entry.dst, entry.slot);
+        match entry.event {
+            Event::Planned => {
+                moves.entry(key).or_insert((planned, false));
+                planned += 1;
+            }
+            Event::Started => {
+                if let Some(state) = moves.get_mut(&key) {
+                    state.1 = true;
+                }
+            }
+            Event::Done => {
+                moves.remove(&key);
+            }
+        }
+    }
+    let mut pending: Vec<(usize, Pending)> = moves
+        .into_iter()
+        .map(|((src, dst, slot), (order, started))| {
+            (
+                order,
+                Pending {
+                    src,
+                    dst,
+                    slot,
+                    started,
+                },
+            )
+        })
+        .collect();
+    pending.sort_by_key(|(order, _)| *order);
+    Ok(pending.into_iter().map(|(_, p)| p).collect())
+}
+
+// resume finish the unfinished moves of the journal at path, or move the
+// slots back to their source with rollback. The journal is removed at the end.
+pub fn resume<P: AsRef<Path>>(path: P, rollback: bool) -> AsResult<()> {
+    let path = path.as_ref();
+    let left = pending(path)?;
+    if left.is_empty() {
+        println!("nothing to resume in {}", path.display());
+        if path.exists() {
+            Journal::open(path)?.finish()?;
+        }
+        return Ok(());
+    }
+    let journal = Journal::open(path)?;
+    let mut nodes: HashMap<String, Node> = HashMap::new();
+    for mv in &left {
+        plan::cached_node(&mut nodes, &mv.src)?;
+        plan::cached_node(&mut nodes, &mv.dst)?;
+        let (src, dst) = (&nodes[&mv.src], &nodes[&mv.dst]);
+        if rollback {
+            println!("roll back slot {} to {}", mv.slot, mv.src);
+            cluster::rollback_slot(src, dst, mv.slot)?;
+        } else {
+            println!("finish slot {} from {} to {}", mv.slot, mv.src, mv.dst);
+            cluster::finish_slot(src, dst, mv.slot)?;
+        }
+        journal.record(Event::Done, &mv.src, &mv.dst, mv.slot)?;
+    }
+    journal.finish()
+}
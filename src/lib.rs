@@ -3,38 +3,223 @@
 #[macro_use]
 extern crate clap;
 extern crate redis;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[cfg_attr(test, macro_use)]
+extern crate serde_json;
+extern crate serde_yaml;
 
 mod add;
-mod cluster;
+pub mod affinity;
+pub mod check;
+pub mod cluster;
+pub mod conn;
 mod create;
+pub mod journal;
+pub mod locate;
+pub mod migrate;
+pub mod plan;
+pub mod preflight;
+pub mod progress;
+mod report;
+pub mod slots;
+pub mod topology;
 mod util;
+pub mod zones;
 
 use add::Add;
-use clap::App;
-use cluster::{Cluster, Node};
+use clap::{App, ArgMatches};
+use cluster::{AsResult, Cluster, Error, Node};
+use conn::{ConnOpts, TlsOpts};
 use create::Create;
-use std::{thread, time};
+use migrate::MigrateOpts;
+use plan::{Plan, PlanFormat};
+use report::Report;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+use topology::Topology;
+use zones::Zones;
+
+#[test]
+fn test_migrate_args() {
+    let yaml = load_yaml!("cli.yml");
+    let matches = App::from_yaml(yaml).get_matches_from(vec![
+        "rckit",
+        "migrate",
+        "-n",
+        "127.0.0.1:1",
+        "-d",
+        "127.0.0.1:2",
+        "-s",
+        "0-10",
+        "--dry-run",
+    ]);
+    let sub_m = matches.subcommand_matches("migrate").unwrap();
+    assert_eq!(sub_m.value_of("slot"), Some("0-10"));
+    assert!(sub_m.is_present("dry-run"));
+    let matches = App::from_yaml(yaml).get_matches_from(vec![
+        "rckit",
+        "migrate",
+        "-d",
+        "127.0.0.1:2",
+        "--keys",
+        "a",
+        "b",
+    ]);
+    let sub_m = matches.subcommand_matches("migrate").unwrap();
+    assert_eq!(sub_m.values_of("keys").unwrap().count(), 2);
+    let matches = App::from_yaml(yaml).get_matches_from(vec!["rckit", "migrate", "-s", "0-10"]);
+    let err = execute(&matches).unwrap_err();
+    assert_eq!(err.exit_code(), 2);
+}
+
+// disabled_node serve INFO and DBSIZE like an empty redis with cluster-enabled
+// no, other commands get an error reply.
+#[cfg(test)]
+fn disabled_node() -> String {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                    let argc: usize = line.trim()[1..].parse().unwrap();
+                    let mut args = Vec::new();
+                    for _ in 0..argc * 2 {
+                        line.clear();
+                        reader.read_line(&mut line).unwrap();
+                        args.push(line.trim().to_uppercase());
+                    }
+                    let info =
+                        "# Server\r\nredis_version:7.2.4\r\n# Cluster\r\ncluster_enabled:0\r\n";
+                    let reply = match args[1].as_str() {
+                        "INFO" => format!("${}\r\n{}\r\n", info.len(), info),
+                        "DBSIZE" => ":0\r\n".to_string(),
+                        _ => "-ERR This instance has cluster support disabled\r\n".to_string(),
+                    };
+                    stream.write_all(reply.as_bytes()).unwrap();
+                    line.clear();
+                }
+            });
+        }
+    });
+    addr
+}
+
+#[test]
+fn test_preflight_disabled() {
+    let yaml = load_yaml!("cli.yml");
+    let addrs: Vec<String> = (0..3).map(|_| disabled_node()).collect();
+    let mut args = vec!["rckit", "create", "--dry-run", "-n"];
+    args.extend(addrs.iter().map(|x| x.as_str()));
+    let err = execute(&App::from_yaml(yaml).get_matches_from(args)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "precondition fail: 3 nodes failed the pre-flight checks"
+    );
+    assert_eq!(err.exit_code(), 7);
+
+    let args = vec![
+        "rckit",
+        "add",
+        "-c",
+        &addrs[0],
+        "-n",
+        &addrs[1],
+        "--dry-run",
+    ];
+    let err = execute(&App::from_yaml(yaml).get_matches_from(args)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "precondition fail: 1 nodes failed the pre-flight checks"
+    );
+}
 
 pub fn run() {
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
+    if let Err(err) = execute(&matches) {
+        eprintln!("error: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+fn execute(matches: &ArgMatches) -> AsResult<()> {
+    let mut opts = ConnOpts::resolve(
+        matches.value_of("user"),
+        matches.value_of("password"),
+        matches.value_of("auth-file"),
+    )?;
+    let tls = TlsOpts {
+        ca_file: matches.value_of("cacert").map(|x| x.to_string()),
+        cert_file: matches.value_of("cert").map(|x| x.to_string()),
+        key_file: matches.value_of("key").map(|x| x.to_string()),
+        insecure: matches.is_present("insecure"),
+    };
+    if matches.is_present("tls") || tls != TlsOpts::default() {
+        opts.tls = Some(tls);
+    }
+    conn::set_opts(opts);
+    let defaults = MigrateOpts::default();
+    let batch = number(matches, "batch")?.unwrap_or(defaults.batch);
+    if batch == 0 {
+        return Err(Error::InvalidArg("bad batch 0".to_string()));
+    }
+    let chunk = number(matches, "chunk")?;
+    if chunk == Some(0) {
+        return Err(Error::InvalidArg("bad chunk 0".to_string()));
+    }
+    progress::set_file(matches.value_of("progress-file").map(|x| x.to_string()));
+    migrate::set_opts(MigrateOpts {
+        batch,
+        timeout: number(matches, "migrate-timeout")?
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.timeout),
+        conflict: matches.value_of("conflict").unwrap().parse()?,
+        copy: matches.is_present("copy"),
+        large_key: number(matches, "large-key")?,
+        large_timeout: number(matches, "large-key-timeout")?
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.large_timeout),
+        chunk,
+        keys_per_sec: number(matches, "keys-per-sec")?,
+        bytes_per_sec: number(matches, "bytes-per-sec")?,
+        max_ops: number(matches, "max-ops")?,
+        max_latency: number(matches, "max-latency")?.map(Duration::from_millis),
+    });
 
     if let Some(sub_m) = matches.subcommand_matches("create") {
-        let slave_count = clap::value_t!(sub_m.value_of("replicate"), usize).unwrap();
-        let mut master_count = clap::value_t!(sub_m.value_of("master"), usize).unwrap();
-        let node: Vec<&str> = sub_m.values_of("node").unwrap().collect();
-        let mut create = Create::new(node, master_count, slave_count).unwrap();
-        create.cluster.check().expect("check node err");
-        create.init_slots();
-        create.add_slots();
-        create.set_config_epoch();
-        create.join_cluster();
-        println!("wait consistent...");
-        while !create.consistent() {
-            thread::sleep(time::Duration::from_secs(1));
-        }
-        create.set_slave().expect("set slave err");
-        return;
+        let slave_count = value_t!(sub_m.value_of("replicate"), usize)
+            .map_err(|err| Error::InvalidArg(err.to_string()))?;
+        let master_count = value_t!(sub_m.value_of("master"), usize)
+            .map_err(|err| Error::InvalidArg(err.to_string()))?;
+        let mut zones = inventory(matches)?;
+        let node = sub_m
+            .values_of("node")
+            .unwrap()
+            .map(|addr| label(&mut zones, addr))
+            .collect::<AsResult<Vec<String>>>()?;
+        preflight(sub_m, &node, None)?;
+        let mut create = Create::new(
+            node.iter().map(|x| x.as_str()).collect(),
+            zones,
+            master_count,
+            slave_count,
+        )?;
+        create.init_slots()?;
+        return run_plan(sub_m, &create.plan());
     }
 
     if let Some(sub_m) = matches.subcommand_matches("add") {
@@ -43,22 +228,33 @@ pub fn run() {
             .expect("must spec existing cluster node");
         let nodes: Vec<&str> = sub_m
             .values_of("node")
-            .expect("must spec at least one node be add to cluster")
+            .ok_or_else(|| {
+                Error::InvalidArg("must spec at least one node be add to cluster".to_string())
+            })?
+            .collect();
+        let mut zones = inventory(matches)?;
+        let nodes = nodes
+            .iter()
+            .map(|pair| {
+                pair.split(',')
+                    .map(|addr| label(&mut zones, addr))
+                    .collect::<AsResult<Vec<String>>>()
+                    .map(|addrs| addrs.join(","))
+            })
+            .collect::<AsResult<Vec<String>>>()?;
+        let addrs: Vec<String> = nodes
+            .iter()
+            .flat_map(|pair| pair.split(','))
+            .map(|addr| addr.to_string())
             .collect();
-        println!("add node {:?} to cluster {}", nodes, cluster);
-        let mut add = Add::new(
+        preflight(sub_m, &addrs, Some(cluster))?;
+        let add = Add::new(
             cluster.to_string(),
-            nodes.iter().map(|x| x.to_string()).collect(),
-        )
-        .unwrap();
-        add.cluster.check().expect("check cluste nodes fail");
-        let _: () = add.add_node().expect("add node fail");
-        while !add.cluster.consistency() {
-            eprintln!("wait consistent fail");
-            thread::sleep(time::Duration::from_secs(1));
-        }
-        add.set_slave();
-        return;
+            nodes,
+            &zones,
+            sub_m.is_present("spread"),
+        )?;
+        return run_plan(sub_m, &add.plan());
     }
 
     if let Some(sub_m) = matches.subcommand_matches("delete") {
@@ -66,124 +262,411 @@ pub fn run() {
             .values_of("node")
             .expect("get node to deleted fail")
             .collect();
-        let new_node = Node::new(newnodes[0].as_bytes()).expect("new node fail");
-        let nodes = new_node.nodes();
-        for n in &nodes {
-            println!("nodes {:?} ", n);
-        }
-        let cluster = Cluster::new(nodes);
-
+        let new_node = Node::new(newnodes[0].as_bytes())?;
+        let cluster = Cluster::new(new_node.nodes()?);
+        let mut del_nodes = Vec::new();
         for node in newnodes {
-            let del_node = cluster.node(node).expect("get node from cluster fail");
-
-            println!("delete node {:?}", del_node);
-            cluster.delete_node(del_node);
+            del_nodes.push(
+                cluster
+                    .node(node)
+                    .ok_or_else(|| Error::Precondition(format!("node {} not in cluster", node)))?,
+            );
         }
-        return;
+        return run_plan(sub_m, &cluster.delete_plan(&del_nodes)?);
     }
 
     if let Some(sub_m) = matches.subcommand_matches("migrate") {
         let arg = (
             sub_m.value_of("node"),
             sub_m.value_of("dst"),
-            clap::value_t!(sub_m.value_of("count"), usize),
+            value_t!(sub_m.value_of("count"), usize),
         );
-        let slot = clap::value_t!(sub_m.value_of("slot"), usize);
-        let migrate = |src: &Node, dst: &Node, count: &[usize]| {
-            for slot in count {
-                cluster::migrate_slot(&src, dst, *slot)
+        let mut plan = Plan::default();
+        let tags: Vec<&str> = sub_m.values_of("tag").map_or(vec![], |v| v.collect());
+        let keys: Vec<&str> = sub_m.values_of("keys").map_or(vec![], |v| v.collect());
+        if !tags.is_empty() || !keys.is_empty() {
+            let dst = sub_m
+                .value_of("dst")
+                .ok_or_else(|| Error::InvalidArg("--tag and --keys need -d".to_string()))?;
+            let mut dst_node = Node::new(dst.as_bytes())?;
+            dst_node.connect()?;
+            let mut slots: Vec<usize> = tags
+                .iter()
+                .map(|tag| slots::tag_slot(tag))
+                .chain(keys.iter().map(|key| slots::key_slot(key.as_bytes())))
+                .collect();
+            slots.sort();
+            slots.dedup();
+            plan.migrate_slots_to(&dst_node.nodes()?, &dst_node, &slots)?;
+            return run_plan(sub_m, &plan);
+        }
+        let masters_except = |node: &Node| -> AsResult<Vec<Node>> {
+            let masters: Vec<Node> = node
+                .nodes()?
+                .into_iter()
+                .filter(|x| x.is_master() && x.is_available() && x.name != node.name)
+                .collect();
+            if masters.is_empty() {
+                return Err(Error::Precondition(format!(
+                    "no other master than {}",
+                    node.addr()
+                )));
             }
+            Ok(masters)
         };
-        if let Ok(slot) = slot {
+        if let Some(expr) = sub_m.value_of("slot") {
+            let slots = slots::parse(expr)?;
             if let (Some(src), Some(dst), _) = arg {
-                let mut src_node = Node::new(src.as_bytes()).unwrap();
-                let mut dst_node = Node::new(dst.as_bytes()).unwrap();
-                src_node.connect();
-                dst_node.connect();
-                return migrate(&mut src_node, &dst_node, &[slot]);
-            } else {
-                println!("{}", matches.usage());
-                return;
+                let mut src_node = Node::new(src.as_bytes())?;
+                let mut dst_node = Node::new(dst.as_bytes())?;
+                src_node.connect()?;
+                dst_node.connect()?;
+                plan.migrate_owned_slots(&src_node, &dst_node, &slots)?;
+                return run_plan(sub_m, &plan);
             }
+            return Err(Error::InvalidArg("-s need -n and -d".to_string()));
         }
         match arg {
             (Some(src), Some(dst), Ok(count)) => {
-                let mut src_node = Node::new(src.as_bytes()).unwrap();
-                let mut dst_node = Node::new(dst.as_bytes()).unwrap();
-                src_node.connect();
-                dst_node.connect();
+                let mut src_node = Node::new(src.as_bytes())?;
+                let mut dst_node = Node::new(dst.as_bytes())?;
+                src_node.connect()?;
+                dst_node.connect()?;
                 let slots = src_node.slots();
-                migrate(&mut src_node, &dst_node, &slots[..count])
+                let count = count.min(slots.len());
+                plan.migrate_slots(&src_node, &dst_node, &slots[..count]);
             }
             (Some(src), _, Ok(count)) => {
-                let mut src_node = Node::new(src.as_bytes()).unwrap();
-                src_node.connect();
-                let src_name = src_node.name.clone();
-                let masters: Vec<Node> = src_node
-                    .nodes()
-                    .into_iter()
-                    .filter(|x| x.is_master() && x.name != src_name)
-                    .collect();
-                let mut dist = util::divide(count, masters.len());
+                let mut src_node = Node::new(src.as_bytes())?;
+                src_node.connect()?;
+                let masters = masters_except(&src_node)?;
+                let slots = src_node.slots();
+                let mut dist = util::divide(count.min(slots.len()), masters.len());
                 let mut idx = 0;
-                let mut slots = src_node.slots();
 
                 for master in masters.into_iter() {
                     let num = dist.pop().unwrap();
                     let migra = &slots[idx..idx + num];
-                    migrate(&mut src_node, &master, migra);
+                    plan.migrate_slots(&src_node, &master, migra);
                     idx += num;
                 }
             }
             (Some(src), Some(dst), Err(_)) => {
-                let mut src_node = Node::new(src.as_bytes()).unwrap();
-                let mut dst_node = Node::new(dst.as_bytes()).unwrap();
-                src_node.connect();
-                dst_node.connect();
+                let mut src_node = Node::new(src.as_bytes())?;
+                let mut dst_node = Node::new(dst.as_bytes())?;
+                src_node.connect()?;
+                dst_node.connect()?;
                 let slots = src_node.slots();
-                migrate(&mut src_node, &dst_node, &slots[..])
+                plan.migrate_slots(&src_node, &dst_node, &slots[..]);
             }
             (None, Some(dst), Ok(count)) => {
-                let mut dst_node = Node::new(dst.as_bytes()).unwrap();
-                let dst_name = dst_node.name.clone();
-                let mut masters: Vec<Node> = dst_node
-                    .nodes()
-                    .clone()
-                    .into_iter()
-                    .filter(|x| x.is_master() && x.name != dst_name)
-                    .collect();
+                let mut dst_node = Node::new(dst.as_bytes())?;
+                dst_node.connect()?;
+                let masters = masters_except(&dst_node)?;
                 let mut slots = util::divide(count, masters.len());
                 for master in masters {
                     let num = slots.pop().unwrap();
                     let slot = master.slots();
-                    migrate(&master, &dst_node, &slot[..num])
+                    plan.migrate_slots(&master, &dst_node, &slot[..num.min(slot.len())]);
+                }
+            }
+            _ => {
+                return Err(Error::InvalidArg(
+                    "migrate need -n with -d or -c, or -d with -c".to_string(),
+                ))
+            }
+        }
+        return run_plan(sub_m, &plan);
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("locate") {
+        let addr = sub_m.value_of("node").unwrap();
+        let mut node = Node::new(addr.as_bytes())?;
+        node.connect()?;
+        let topology = node.topology()?;
+        let mut owners = HashMap::new();
+        for key in sub_m.values_of("keys").unwrap() {
+            let mut location = locate::locate(&topology, key);
+            if let (true, Some(master)) = (sub_m.is_present("info"), &location.master) {
+                location.state = plan::cached_node(&mut owners, master)?.key_state(key)?;
+            }
+            println!("{}", location);
+        }
+        return Ok(());
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("slot") {
+        let addr = sub_m.value_of("node").unwrap();
+        let sample = number(sub_m, "sample")?.unwrap_or(10);
+        let (views, problems) = check::gather(&Node::new(addr.as_bytes())?)?;
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        // one node per address, so each is connected once for all slots.
+        let mut nodes = HashMap::new();
+        for view in &views {
+            let addrs = view.topology.nodes.iter().map(|entry| &entry.addr);
+            for addr in addrs.chain(Some(&view.addr)) {
+                if !nodes.contains_key(addr) {
+                    nodes.insert(addr.clone(), Node::new(addr.as_bytes())?);
+                }
+            }
+        }
+        for slot in slots::parse(sub_m.value_of("slot").unwrap())? {
+            let report = check::inspect_slot(&views, slot);
+            println!("{}", report);
+            for addr in report.nodes() {
+                let node = nodes.get(addr).ok_or(Error::BadAddr)?;
+                let count = node.countkeysinslot(slot)?;
+                let keys = node.keysinslot(slot, sample)?;
+                if keys.is_empty() {
+                    println!("{} holds {} keys", addr, count);
+                } else {
+                    println!("{} holds {} keys: {}", addr, count, keys.join(", "));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("epochs") {
+        let addr = sub_m.value_of("node").unwrap();
+        let topology = Node::new(addr.as_bytes())?.topology()?;
+        let duplicates = check::duplicate_epochs(&topology);
+        if duplicates.is_empty() {
+            println!(
+                "config epochs of {} masters are unique",
+                topology.masters().count()
+            );
+            return Ok(());
+        }
+        for (epoch, nodes) in &duplicates {
+            println!("config epoch {} shared by {}", epoch, nodes.join(","));
+        }
+        if !sub_m.is_present("fix") {
+            return Err(Error::Precondition(format!(
+                "{} config epochs are shared",
+                duplicates.len()
+            )));
+        }
+        // the first node of each group keep its epoch like in redis own
+        // collision handling.
+        for (_, nodes) in &duplicates {
+            for addr in &nodes[1..] {
+                let node = Node::new(addr.as_bytes())?;
+                if !node.bump_epoch()? {
+                    println!(
+                        "{} already has the highest epoch, redis resolves it by itself",
+                        addr
+                    );
+                    continue;
                 }
+                let epoch = node.topology()?.myself().map_or(0, |n| n.config_epoch);
+                println!("config epoch of {} bumped to {}", addr, epoch);
             }
-            _ => println!("err"),
         }
-        return;
+        return Ok(());
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("affinity") {
+        let addr = sub_m.value_of("node").unwrap();
+        let zones = inventory(matches)?;
+        let shards = affinity::shards(&Node::new(addr.as_bytes())?.topology()?);
+        let findings = affinity::audit(&shards, &zones);
+        if findings.is_empty() {
+            println!(
+                "copies of {} shards are on distinct hosts and zones",
+                shards.len()
+            );
+            return Ok(());
+        }
+        for finding in &findings {
+            println!("{}", finding);
+        }
+        if !sub_m.is_present("fix") {
+            return Err(Error::Precondition(format!(
+                "{} placement problems",
+                findings.len()
+            )));
+        }
+        let repair = affinity::repair(&shards, &zones);
+        for master in &repair.unsafe_shards {
+            eprintln!(
+                "warning: shard {} has no copy out of the failure domain of its master, add nodes in other failure domains to fix it",
+                master
+            );
+        }
+        return run_plan(sub_m, &repair.plan);
     }
 
     if let Some(sub_m) = matches.subcommand_matches("fix") {
         let addr = sub_m.value_of("node").expect("get node err");
-        let mut node = Node::new(addr.as_bytes()).unwrap();
-        node.connect();
-        let nodes = node.nodes();
+        let mut node = Node::new(addr.as_bytes())?;
+        node.connect()?;
+        let nodes = node.nodes()?;
         let cluster = Cluster::new(nodes);
-        cluster.fix_slots();
-        cluster.fill_slots();
-        return;
+        cluster.fix_slots()?;
+        return cluster.fill_slots();
     }
 
     if let Some(sub_m) = matches.subcommand_matches("reshard") {
         let addr = sub_m.value_of("node").expect("get node err");
-        let mut node = Node::new(addr.as_bytes()).unwrap();
-        node.connect();
-        let nodes = node.nodes();
+        let mut node = Node::new(addr.as_bytes())?;
+        node.connect()?;
+        let nodes = node.nodes()?;
         let cluster = Cluster::new(nodes);
-        cluster.reshard();
-        return;
+        return run_plan(sub_m, &cluster.reshard_plan()?);
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("apply") {
+        let plan = Plan::from_file(sub_m.value_of("plan").unwrap())?;
+        return run_plan(sub_m, &plan);
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("resume") {
+        let path = journal_path(sub_m);
+        if sub_m.is_present("dry-run") {
+            for mv in journal::pending(path)? {
+                let state = if mv.started { "started" } else { "planned" };
+                println!("slot {} from {} to {} {}", mv.slot, mv.src, mv.dst, state);
+            }
+            return Ok(());
+        }
+        return journal::resume(path, sub_m.is_present("rollback"));
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("report") {
+        let addr = sub_m.value_of("node").expect("get node err");
+        let report = Report::new(
+            sub_m.value_of("column").unwrap(),
+            sub_m.value_of("format").unwrap(),
+        )?;
+        let mut node = Node::new(addr.as_bytes())?;
+        node.connect()?;
+        let rows = report.collect(&node)?;
+        let mut output: Box<dyn Write> = match sub_m.value_of("output") {
+            Some("stdout") | None => Box::new(io::stdout()),
+            Some(path) => {
+                Box::new(File::create(path).map_err(|err| Error::Io(path.to_string(), err))?)
+            }
+        };
+        return report
+            .write(&mut output, &rows)
+            .map_err(|err| Error::Io(sub_m.value_of("output").unwrap().to_string(), err));
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("check") {
+        let (views, mut problems) = match (sub_m.value_of("node"), sub_m.value_of("file")) {
+            (_, Some(path)) => {
+                let view = check::View {
+                    addr: path.to_string(),
+                    topology: Topology::from_file(path)?,
+                };
+                (vec![view], vec![])
+            }
+            (Some(addr), None) => check::gather(&Node::new(addr.as_bytes())?)?,
+            (None, None) => {
+                return Err(Error::InvalidArg(
+                    "check need -n <node> or -f <nodes.conf>".to_string(),
+                ))
+            }
+        };
+        problems.extend(check::check(&views));
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        if !problems.is_empty() {
+            return Err(Error::Precondition(format!(
+                "{} problems found in {} views",
+                problems.len(),
+                views.len()
+            )));
+        }
+        println!("cluster ok, {} views agree", views.len());
+        return Ok(());
+    }
+
+    println!("{}", matches.usage());
+    Ok(())
+}
+
+// run_plan save the plan with --save-plan, print it with --dry-run, or
+// execute it.
+fn run_plan(matches: &ArgMatches, plan: &Plan) -> AsResult<()> {
+    if let Some(path) = matches.value_of("save-plan") {
+        let mut file = File::create(path).map_err(|err| Error::Io(path.to_string(), err))?;
+        plan.write(&mut file, PlanFormat::from_path(path))
+            .map_err(|err| Error::Io(path.to_string(), err))?;
+        println!("plan of {} steps saved to {}", plan.steps.len(), path);
+        return Ok(());
     }
+    if matches.is_present("dry-run") {
+        let format: PlanFormat = matches.value_of("plan-format").unwrap().parse()?;
+        return plan
+            .write(&mut io::stdout(), format)
+            .map_err(|err| Error::Io("stdout".to_string(), err));
+    }
+    let parallel = match matches.value_of("parallel") {
+        Some(n) => n
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| Error::InvalidArg(format!("bad parallel {}", n)))?,
+        None => 1,
+    };
+    plan.execute(journal_path(matches), parallel)
+}
+
+// preflight check addrs before they join the cluster of reference, or a new
+// cluster, and before anything else query them. The report is printed on
+// stderr when stdout carries a json or yaml plan, and fail when a node is not
+// fit to join a cluster.
+fn preflight(matches: &ArgMatches, addrs: &[String], reference: Option<&str>) -> AsResult<()> {
+    let nodes = addrs
+        .iter()
+        .map(|addr| Node::new(addr.as_bytes()))
+        .collect::<AsResult<Vec<Node>>>()?;
+    let reference = match reference {
+        Some(addr) => Some(Node::new(addr.as_bytes())?),
+        None => None,
+    };
+    let report = preflight::run(&nodes, reference.as_ref())?;
+    if matches.is_present("dry-run") && matches.value_of("plan-format") != Some("human") {
+        eprint!("{}", report);
+    } else {
+        print!("{}", report);
+    }
+    report.result()
+}
+
+// inventory load the zones of --inventory, empty without it.
+fn inventory(matches: &ArgMatches) -> AsResult<Zones> {
+    match matches.value_of("inventory") {
+        Some(path) => Zones::load(path),
+        None => Ok(Zones::default()),
+    }
+}
+
+// label strip the zone of a host:port@zone address into zones.
+fn label(zones: &mut Zones, arg: &str) -> AsResult<String> {
+    let (addr, zone) = zones::split_zone(arg)?;
+    if let Some(zone) = zone {
+        zones.insert(&addr, &zone)?;
+    }
+    Ok(addr)
+}
+
+// number parse the optional numeric argument name.
+fn number<T: FromStr>(matches: &ArgMatches, name: &str) -> AsResult<Option<T>> {
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| Error::InvalidArg(format!("bad {} {}", name, value))),
+        None => Ok(None),
+    }
+}
 
-    println!("{}", matches.usage())
+fn journal_path<'a>(matches: &'a ArgMatches) -> &'a str {
+    matches.value_of("journal").unwrap_or(journal::DEFAULT_PATH)
 }
@@ -0,0 +1,116 @@
+use slots;
+use std::fmt;
+use topology::Topology;
+
+#[test]
+fn test_locate() {
+    let text = "\
+aaaa 127.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-8191 [5061->-bbbb]
+bbbb 127.0.0.1:7001@17001 master - 0 0 2 connected 8192-16383
+cccc 127.0.0.1:7002@17002 slave aaaa 0 0 1 connected
+dddd 127.0.0.1:7003@17003 slave aaaa 0 0 1 connected
+";
+    let topology = Topology::parse(text).unwrap();
+    let mut location = locate(&topology, "bar");
+    assert_eq!(location.slot, 5061);
+    assert_eq!(location.master, Some("127.0.0.1:7000".to_string()));
+    assert_eq!(location.replicas.len(), 2);
+    assert_eq!(
+        location.to_string(),
+        "bar slot 5061 master 127.0.0.1:7000 replicas 127.0.0.1:7002,127.0.0.1:7003 \
+         migrating to 127.0.0.1:7001"
+    );
+    location.state = KeyState::Found {
+        kind: "hash".to_string(),
+        ttl: 1500,
+        memory: 72,
+    };
+    assert!(location
+        .to_string()
+        .ends_with("type hash ttl 1500ms memory 72"));
+    let mut location = locate(&topology, "foo");
+    location.state = KeyState::Missing;
+    assert_eq!(
+        location.to_string(),
+        "foo slot 12182 master 127.0.0.1:7001 replicas none missing"
+    );
+    let topology = Topology::parse(&text.replace("8192-16383", "")).unwrap();
+    assert_eq!(locate(&topology, "foo").master, None);
+}
+
+// KeyState is what the owner know about the key, NotChecked unless asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyState {
+    NotChecked,
+    Missing,
+    // ttl is the PTTL of the key, negative without expire.
+    Found { kind: String, ttl: i64, memory: u64 },
+}
+
+// Location is where a key live in the view of one node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub key: String,
+    pub slot: usize,
+    pub master: Option<String>,
+    pub replicas: Vec<String>,
+    // migrating is the node the slot is being moved to.
+    pub migrating: Option<String>,
+    pub state: KeyState,
+}
+
+// locate find the slot of key and the nodes serving it in topology.
+pub fn locate(topology: &Topology, key: &str) -> Location {
+    let slot = slots::key_slot(key.as_bytes());
+    let master = topology.masters().find(|m| m.slots.contains(&slot));
+    let replicas = match master {
+        Some(master) => topology
+            .nodes
+            .iter()
+            .filter(|n| n.master.as_ref() == Some(&master.id))
+            .map(|n| n.addr.clone())
+            .collect(),
+        None => vec![],
+    };
+    let migrating = master
+        .and_then(|m| m.migrating.get(&slot))
+        .map(|peer| topology.node(peer).map_or(peer.clone(), |n| n.addr.clone()));
+    Location {
+        key: key.to_string(),
+        slot,
+        master: master.map(|m| m.addr.clone()),
+        replicas,
+        migrating,
+        state: KeyState::NotChecked,
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} slot {} master {} replicas {}",
+            self.key,
+            self.slot,
+            self.master.as_ref().map_or("none", |m| m.as_str()),
+            if self.replicas.is_empty() {
+                "none".to_string()
+            } else {
+                self.replicas.join(",")
+            }
+        )?;
+        if let Some(peer) = &self.migrating {
+            write!(f, " migrating to {}", peer)?;
+        }
+        match &self.state {
+            KeyState::NotChecked => Ok(()),
+            KeyState::Missing => write!(f, " missing"),
+            KeyState::Found { kind, ttl, memory } if *ttl < 0 => {
+                write!(f, " type {} ttl none memory {}", kind, memory)
+            }
+            KeyState::Found { kind, ttl, memory } => {
+                write!(f, " type {} ttl {}ms memory {}", kind, ttl, memory)
+            }
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use cluster::{AsResult, Error, Node};
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_pacer() {
+    let opts = MigrateOpts {
+        keys_per_sec: Some(100),
+        bytes_per_sec: Some(1000),
+        ..MigrateOpts::default()
+    };
+    let now = Instant::now();
+    let mut pacer = Pacer::default();
+    assert_eq!(pacer.add(100, 500, &opts, now), Duration::from_secs(1));
+    // bytes are the bottleneck.
+    assert_eq!(
+        pacer.add(10, 1500, &opts, now + Duration::from_secs(1)),
+        Duration::from_secs(1)
+    );
+    // credit of a long pause is not kept.
+    assert_eq!(
+        pacer.add(50, 0, &opts, now + Duration::from_secs(60)),
+        Duration::from_millis(500)
+    );
+    let mut pacer = Pacer::default();
+    assert_eq!(
+        pacer.add(100, 500, &MigrateOpts::default(), now),
+        Duration::from_secs(0)
+    );
+}
+
+#[test]
+fn test_backoff() {
+    let opts = MigrateOpts {
+        max_ops: Some(1000),
+        max_latency: Some(Duration::from_millis(5)),
+        ..MigrateOpts::default()
+    };
+    assert!(!busy(&opts, 999, Duration::from_millis(1)));
+    assert!(busy(&opts, 1001, Duration::from_millis(1)));
+    assert!(busy(&opts, 10, Duration::from_millis(6)));
+    assert!(!busy(
+        &MigrateOpts::default(),
+        100_000,
+        Duration::from_secs(1)
+    ));
+    let mut delay = Duration::from_secs(0);
+    for _ in 0..10 {
+        delay = next_delay(delay);
+    }
+    assert_eq!(next_delay(Duration::from_secs(0)), MIN_BACKOFF);
+    assert_eq!(delay, MAX_BACKOFF);
+}
+
+#[test]
+fn test_migrate_args() {
+    let keys = vec!["a".to_string(), "b".to_string()];
+    let opts = MigrateOpts::default();
+    assert_eq!(
+        migrate_args("127.0.0.1", "7001", &keys, &opts, None).join(" "),
+        "127.0.0.1 7001  0 5000 KEYS a b"
+    );
+    let opts = MigrateOpts {
+        timeout: Duration::from_secs(60),
+        conflict: Conflict::Replace,
+        copy: true,
+        ..MigrateOpts::default()
+    };
+    let auth = vec!["admin".to_string(), "secret".to_string()];
+    assert_eq!(
+        migrate_args("::1", "7001", &keys[..1], &opts, Some(auth)).join(" "),
+        "::1 7001  0 60000 COPY REPLACE AUTH2 admin secret KEYS a"
+    );
+    assert_eq!("skip".parse::<Conflict>().unwrap(), Conflict::Skip);
+    assert!("overwrite".parse::<Conflict>().is_err());
+}
+
+#[test]
+fn test_large_keys() {
+    let keys: Vec<String> = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+    let (large, small) = split_large(&keys, &[10, 2000, 30], Some(1000));
+    assert_eq!(large, vec!["b".to_string()]);
+    assert_eq!(small, vec!["a".to_string(), "c".to_string()]);
+    let (large, small) = split_large(&keys, &[0, 0, 0], None);
+    assert!(large.is_empty());
+    assert_eq!(small.len(), 3);
+    assert_eq!(Collection::parse("zset"), Some(Collection::ZSet));
+    assert_eq!(Collection::parse("stream"), None);
+    let items = vec![
+        b"m1".to_vec(),
+        b"1".to_vec(),
+        b"m2".to_vec(),
+        b"2.5".to_vec(),
+    ];
+    assert_eq!(
+        add_args(Collection::ZSet, items.clone()),
+        vec![
+            b"1".to_vec(),
+            b"m1".to_vec(),
+            b"2.5".to_vec(),
+            b"m2".to_vec()
+        ]
+    );
+    assert_eq!(add_args(Collection::Hash, items.clone()), items);
+}
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+// MigrateOpts tune how keys are moved by every slot migration of the process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrateOpts {
+    // batch is the number of keys sent by one MIGRATE.
+    pub batch: usize,
+    pub timeout: Duration,
+    pub conflict: Conflict,
+    // copy keep the keys on the source until the whole slot is copied to the
+    // destination, then they are moved again without COPY to hand over the
+    // writes made meanwhile.
+    pub copy: bool,
+    // large_key is the MEMORY USAGE above which a key is moved alone with
+    // large_timeout, or rebuilt chunk elements at a time when chunk is set
+    // and the key is a collection.
+    pub large_key: Option<u64>,
+    pub large_timeout: Duration,
+    pub chunk: Option<usize>,
+    // keys_per_sec and bytes_per_sec limit all moves together, bytes are
+    // measured with MEMORY USAGE.
+    pub keys_per_sec: Option<u64>,
+    pub bytes_per_sec: Option<u64>,
+    // max_ops and max_latency pause the moves from a source node while its
+    // instantaneous_ops_per_sec or PING round trip is above them.
+    pub max_ops: Option<u64>,
+    pub max_latency: Option<Duration>,
+}
+
+const DEFAULT_OPTS: MigrateOpts = MigrateOpts {
+    batch: 100,
+    timeout: Duration::from_millis(5000),
+    conflict: Conflict::Fail,
+    copy: false,
+    large_key: None,
+    large_timeout: Duration::from_secs(60),
+    chunk: None,
+    keys_per_sec: None,
+    bytes_per_sec: None,
+    max_ops: None,
+    max_latency: None,
+};
+
+impl Default for MigrateOpts {
+    fn default() -> MigrateOpts {
+        DEFAULT_OPTS
+    }
+}
+
+// Conflict decide what to do with a key that already exists on the destination.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conflict {
+    // Fail stop the slot move at the first key that can't be moved.
+    Fail,
+    Replace,
+    // Skip log the keys that can't be moved and leave them on the source, the
+    // slot stays open until they are dealt with.
+    Skip,
+}
+
+impl FromStr for Conflict {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Conflict, Error> {
+        match s {
+            "fail" => Ok(Conflict::Fail),
+            "replace" => Ok(Conflict::Replace),
+            "skip" => Ok(Conflict::Skip),
+            other => Err(Error::InvalidArg(format!(
+                "unknown conflict policy {}",
+                other
+            ))),
+        }
+    }
+}
+
+static OPTS: RwLock<MigrateOpts> = RwLock::new(DEFAULT_OPTS);
+
+static PACER: Mutex<Pacer> = Mutex::new(Pacer {
+    start: None,
+    keys: 0,
+    bytes: 0,
+});
+
+pub fn set_opts(opts: MigrateOpts) {
+    *OPTS.write().unwrap() = opts;
+}
+
+pub fn opts() -> MigrateOpts {
+    OPTS.read().unwrap().clone()
+}
+
+// Pacer spread the keys and bytes sent since start at the configured rates.
+#[derive(Debug, Default)]
+struct Pacer {
+    start: Option<Instant>,
+    keys: u64,
+    bytes: u64,
+}
+
+impl Pacer {
+    // add count a batch about to be sent at now and return how long to wait first.
+    fn add(&mut self, keys: u64, bytes: u64, opts: &MigrateOpts, now: Instant) -> Duration {
+        let start = *self.start.get_or_insert(now);
+        let elapsed = now.duration_since(start);
+        let due = self.due(opts);
+        // a pause longer than a second must not be followed by a burst.
+        if elapsed > due + Duration::from_secs(1) {
+            self.start = Some(now);
+            self.keys = 0;
+            self.bytes = 0;
+            return self.add(keys, bytes, opts, now);
+        }
+        self.keys += keys;
+        self.bytes += bytes;
+        self.due(opts).checked_sub(elapsed).unwrap_or_default()
+    }
+
+    // due is the time the data counted so far may take at the configured rates.
+    fn due(&self, opts: &MigrateOpts) -> Duration {
+        let at = |count: u64, rate: Option<u64>| match rate {
+            Some(rate) if rate > 0 => Duration::from_secs_f64(count as f64 / rate as f64),
+            _ => Duration::default(),
+        };
+        at(self.keys, opts.keys_per_sec).max(at(self.bytes, opts.bytes_per_sec))
+    }
+}
+
+// throttle block until keys keys of bytes may be migrated without going over
+// the keys/sec and bytes/sec limits.
+pub fn throttle(keys: usize, bytes: u64) {
+    let opts = opts();
+    if opts.keys_per_sec.is_none() && opts.bytes_per_sec.is_none() {
+        return;
+    }
+    let wait = PACER
+        .lock()
+        .unwrap()
+        .add(keys as u64, bytes, &opts, Instant::now());
+    thread::sleep(wait);
+}
+
+// split_large separate the keys whose size is above limit, sizes are in the
+// order of keys.
+pub fn split_large(
+    keys: &[String],
+    sizes: &[u64],
+    limit: Option<u64>,
+) -> (Vec<String>, Vec<String>) {
+    let (large, small): (Vec<_>, Vec<_>) = keys
+        .iter()
+        .zip(sizes)
+        .partition(|(_, size)| limit.is_some_and(|limit| **size > limit));
+    let keys = |items: Vec<(&String, &u64)>| items.into_iter().map(|(k, _)| k.clone()).collect();
+    (keys(large), keys(small))
+}
+
+// Collection is a key type that can be rebuilt on the destination a chunk of
+// elements at a time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Collection {
+    Hash,
+    Set,
+    ZSet,
+    List,
+}
+
+impl Collection {
+    // parse the reply of TYPE, strings and streams are None.
+    pub fn parse(kind: &str) -> Option<Collection> {
+        match kind {
+            "hash" => Some(Collection::Hash),
+            "set" => Some(Collection::Set),
+            "zset" => Some(Collection::ZSet),
+            "list" => Some(Collection::List),
+            _ => None,
+        }
+    }
+
+    // scan is the command reading the elements page by page, lists are read
+    // with LRANGE instead.
+    pub fn scan(self) -> Option<&'static str> {
+        match self {
+            Collection::Hash => Some("HSCAN"),
+            Collection::Set => Some("SSCAN"),
+            Collection::ZSet => Some("ZSCAN"),
+            Collection::List => None,
+        }
+    }
+
+    // add is the command appending elements as returned by scan, or LRANGE for a list.
+    pub fn add(self) -> &'static str {
+        match self {
+            Collection::Hash => "HSET",
+            Collection::Set => "SADD",
+            Collection::ZSet => "ZADD",
+            Collection::List => "RPUSH",
+        }
+    }
+}
+
+// add_args order scanned elements as the add command expect them, ZSCAN
+// return member and score while ZADD take score first.
+pub fn add_args(kind: Collection, items: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    if kind != Collection::ZSet {
+        return items;
+    }
+    items
+        .chunks(2)
+        .flat_map(|pair| pair.iter().rev().cloned())
+        .collect()
+}
+
+// migrate_args is the arguments of the MIGRATE moving keys to ip:port, auth
+// is passed on so the source can log in to the destination.
+pub fn migrate_args(
+    ip: &str,
+    port: &str,
+    keys: &[String],
+    opts: &MigrateOpts,
+    auth: Option<Vec<String>>,
+) -> Vec<String> {
+    let mut args = vec![
+        ip.to_string(),
+        port.to_string(),
+        String::new(),
+        "0".to_string(),
+        opts.timeout.as_millis().to_string(),
+    ];
+    if opts.copy {
+        args.push("COPY".to_string());
+    }
+    if opts.conflict == Conflict::Replace {
+        args.push("REPLACE".to_string());
+    }
+    if let Some(auth) = auth {
+        args.push(if auth.len() == 2 { "AUTH2" } else { "AUTH" }.to_string());
+        args.extend(auth);
+    }
+    args.push("KEYS".to_string());
+    args.extend(keys.iter().cloned());
+    args
+}
+
+fn busy(opts: &MigrateOpts, ops: u64, latency: Duration) -> bool {
+    opts.max_ops.is_some_and(|max| ops > max) || opts.max_latency.is_some_and(|max| latency > max)
+}
+
+fn next_delay(delay: Duration) -> Duration {
+    (delay * 2).clamp(MIN_BACKOFF, MAX_BACKOFF)
+}
+
+// Backoff pause the move of a slot while its source node is busy, the node is
+// probed at most once a second.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    last_probe: Option<Instant>,
+    delay: Duration,
+}
+
+impl Backoff {
+    pub fn wait(&mut self, src: &Node) -> AsResult<()> {
+        let opts = opts();
+        if opts.max_ops.is_none() && opts.max_latency.is_none() {
+            return Ok(());
+        }
+        loop {
+            if self
+                .last_probe
+                .is_some_and(|last| last.elapsed() < PROBE_INTERVAL)
+            {
+                return Ok(());
+            }
+            let latency = src.ping()?;
+            let ops = src
+                .stats()?
+                .get("instantaneous_ops_per_sec")
+                .and_then(|ops| ops.parse::<u64>().ok())
+                .unwrap_or_default();
+            if !busy(&opts, ops, latency) {
+                self.last_probe = Some(Instant::now());
+                self.delay = Duration::default();
+                return Ok(());
+            }
+            self.delay = next_delay(self.delay);
+            println!(
+                "{} is busy with {} ops/sec and {}ms latency, pause {}ms",
+                src.addr(),
+                ops,
+                latency.as_millis(),
+                self.delay.as_millis()
+            );
+            thread::sleep(self.delay);
+        }
+    }
+}
@@ -0,0 +1,1015 @@
+use cluster::{self, AsResult, Cluster, Error, Node};
+use journal::{Event, Journal};
+use progress;
+use serde_json;
+use serde_yaml;
+use slots;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::{thread, time};
+use topology::{self, NodeEntry, Topology};
+
+#[test]
+fn test_write_human() {
+    let mut plan = Plan::default();
+    plan.push(Step::AddSlots {
+        node: "127.0.0.1:7000".to_string(),
+        slots: vec![(0, 5460), (5462, 5462)],
+    });
+    plan.push(Step::Meet {
+        node: "127.0.0.1:7000".to_string(),
+        peer: "127.0.0.1:7001".to_string(),
+    });
+    plan.push(Step::Replicate {
+        node: "127.0.0.1:7003".to_string(),
+        master: "127.0.0.1:7000".to_string(),
+    });
+    let mut out = Vec::new();
+    plan.write(&mut out, PlanFormat::Human).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\
+1. add slots 0-5460,5462 to 127.0.0.1:7000
+2. 127.0.0.1:7000 meet 127.0.0.1:7001
+3. 127.0.0.1:7003 replicate 127.0.0.1:7000
+3 steps, nothing was changed
+"
+    );
+}
+
+#[test]
+fn test_write_json() {
+    let mut plan = Plan::default();
+    plan.push(Step::MigrateSlot {
+        src: "127.0.0.1:7000".to_string(),
+        dst: "127.0.0.1:7001".to_string(),
+        slot: 42,
+    });
+    plan.push(Step::Forget {
+        node: "127.0.0.1:7001".to_string(),
+        target: "127.0.0.1:7000".to_string(),
+        id: "aaaa".to_string(),
+    });
+    let mut out = Vec::new();
+    plan.write(&mut out, PlanFormat::Json).unwrap();
+    let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(
+        value,
+        json!({"steps": [
+            {"op": "migrate_slot", "src": "127.0.0.1:7000", "dst": "127.0.0.1:7001", "slot": 42},
+            {"op": "forget", "node": "127.0.0.1:7001", "target": "127.0.0.1:7000", "id": "aaaa"},
+        ]})
+    );
+    assert!("xml".parse::<PlanFormat>().is_err());
+}
+
+#[test]
+fn test_parse_yaml() {
+    let text = "\
+steps:
+  - op: set_slot
+    node: 127.0.0.1:7001
+    slot: 42
+    state: importing
+    peer: 127.0.0.1:7000
+  - op: migrate_keys
+    src: 127.0.0.1:7000
+    dst: 127.0.0.1:7001
+    slot: 42
+  - op: set_slot
+    node: 127.0.0.1:7000
+    slot: 42
+    state: stable
+";
+    let plan = Plan::parse(text, PlanFormat::Yaml).unwrap();
+    assert_eq!(plan.steps.len(), 3);
+    assert_eq!(
+        plan.steps[2],
+        Step::SetSlot {
+            node: "127.0.0.1:7000".to_string(),
+            slot: 42,
+            state: SlotState::Stable,
+            peer: None,
+        }
+    );
+    let mut out = Vec::new();
+    plan.write(&mut out, PlanFormat::Json).unwrap();
+    let json = String::from_utf8(out).unwrap();
+    assert_eq!(Plan::parse(&json, PlanFormat::Json).unwrap(), plan);
+    assert!(Plan::parse("steps: [{op: reboot}]", PlanFormat::Yaml).is_err());
+    assert_eq!(PlanFormat::from_path("plan.yml"), PlanFormat::Yaml);
+    assert_eq!(PlanFormat::from_path("plan.json"), PlanFormat::Json);
+}
+
+#[test]
+fn test_check_step() {
+    let text = "\
+aaaa 127.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-100 [42->-bbbb]
+bbbb 127.0.0.1:7001@17001 master - 0 0 2 connected 101-16382
+";
+    let other = "\
+aaaa 127.0.0.1:7000@17000 master - 0 0 1 connected 0-100
+bbbb 127.0.0.1:7001@17001 myself,master - 0 0 2 connected 101-16382 [42-<-aaaa]
+";
+    let replica = "\
+aaaa 127.0.0.1:7000@17000 master - 0 0 1 connected 0-100
+cccc 127.0.0.1:7002@17002 myself,slave aaaa 0 0 1 connected
+";
+    let full = "\
+dddd 127.0.0.1:7003@17003 myself,master - 0 0 3 connected 0-8191
+eeee 127.0.0.1:7004@17004 master - 0 0 4 connected 8192-16383
+";
+    let full_other = "\
+dddd 127.0.0.1:7003@17003 master - 0 0 3 connected 0-8191
+eeee 127.0.0.1:7004@17004 myself,master - 0 0 4 connected 8192-16383
+";
+    let mut view = |addr: &str| -> AsResult<Topology> {
+        match addr {
+            "127.0.0.1:7000" => Topology::parse(text),
+            "127.0.0.1:7001" => Topology::parse(other),
+            "127.0.0.1:7002" => Topology::parse(replica),
+            "127.0.0.1:7003" => Topology::parse(full),
+            "127.0.0.1:7004" => Topology::parse(full_other),
+            _ => Err(Error::BadAddr),
+        }
+    };
+    let migrate = |src: &str, dst: &str, slot: usize| Step::MigrateSlot {
+        src: src.to_string(),
+        dst: dst.to_string(),
+        slot,
+    };
+    assert!(migrate("127.0.0.1:7000", "127.0.0.1:7001", 42)
+        .check(&mut view)
+        .unwrap());
+    // already moved
+    assert!(!migrate("127.0.0.1:7000", "127.0.0.1:7001", 200)
+        .check(&mut view)
+        .unwrap());
+    assert!(migrate("127.0.0.1:7000", "127.0.0.1:7001", 16383)
+        .check(&mut view)
+        .is_err());
+    let keys = |src: &str, dst: &str| Step::MigrateKeys {
+        src: src.to_string(),
+        dst: dst.to_string(),
+        slot: 42,
+    };
+    assert!(keys("127.0.0.1:7000", "127.0.0.1:7001")
+        .check(&mut view)
+        .unwrap());
+    assert!(keys("127.0.0.1:7001", "127.0.0.1:7000")
+        .check(&mut view)
+        .is_err());
+    let add = |slots| Step::AddSlots {
+        node: "127.0.0.1:7000".to_string(),
+        slots,
+    };
+    assert!(!add(vec![(0, 100)]).check(&mut view).unwrap());
+    assert!(add(vec![(100, 101)]).check(&mut view).is_err());
+    let forget = |id: &str| Step::Forget {
+        node: "127.0.0.1:7000".to_string(),
+        target: "127.0.0.1:7009".to_string(),
+        id: id.to_string(),
+    };
+    assert!(forget("bbbb").check(&mut view).unwrap());
+    assert!(!forget("cccc").check(&mut view).unwrap());
+    assert!(forget("aaaa").check(&mut view).is_err());
+    let failover = |node: &str| Step::Failover {
+        node: node.to_string(),
+    };
+    assert!(failover("127.0.0.1:7002").check(&mut view).unwrap());
+    assert!(!failover("127.0.0.1:7000").check(&mut view).unwrap());
+    let set_slot = |node: &str, slot, state, peer: &str| Step::SetSlot {
+        node: node.to_string(),
+        slot,
+        state,
+        peer: Some(peer.to_string()),
+    };
+    let (src, dst) = ("127.0.0.1:7000", "127.0.0.1:7001");
+    assert!(!set_slot(dst, 42, SlotState::Importing, src)
+        .check(&mut view)
+        .unwrap());
+    assert!(!set_slot(src, 42, SlotState::Migrating, dst)
+        .check(&mut view)
+        .unwrap());
+    assert!(set_slot(src, 43, SlotState::Migrating, dst)
+        .check(&mut view)
+        .unwrap());
+    assert!(set_slot(src, 42, SlotState::Node, dst)
+        .check(&mut view)
+        .unwrap());
+    // already given to dst in the view of src.
+    assert!(!set_slot(src, 200, SlotState::Node, dst)
+        .check(&mut view)
+        .unwrap());
+    let wait = |nodes: &[&str]| Step::WaitConsistent {
+        nodes: nodes.iter().map(|x| x.to_string()).collect(),
+    };
+    assert!(!wait(&["127.0.0.1:7003", "127.0.0.1:7004"])
+        .check(&mut view)
+        .unwrap());
+    // slot 16383 is not covered.
+    assert!(wait(&["127.0.0.1:7000", "127.0.0.1:7003"])
+        .check(&mut view)
+        .unwrap());
+}
+
+#[test]
+fn test_migrate_slot_owners() {
+    let text = "\
+aaaa 127.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-10000
+bbbb 127.0.0.1:7001@17001 master - 0 0 2 connected 10001-16383
+cccc 127.0.0.1:7002@17002 master - 0 0 3 connected
+";
+    let nodes: Vec<Node> = Topology::parse(text)
+        .unwrap()
+        .nodes
+        .iter()
+        .map(|entry| Node::from_entry(entry).unwrap())
+        .collect();
+    let mut plan = Plan::default();
+    plan.migrate_slots_to(&nodes, &nodes[2], &[5, 12000])
+        .unwrap();
+    plan.migrate_slots_to(&nodes, &nodes[0], &[6]).unwrap();
+    assert_eq!(
+        plan.steps,
+        vec![
+            Step::MigrateSlot {
+                src: "127.0.0.1:7000".to_string(),
+                dst: "127.0.0.1:7002".to_string(),
+                slot: 5,
+            },
+            Step::MigrateSlot {
+                src: "127.0.0.1:7001".to_string(),
+                dst: "127.0.0.1:7002".to_string(),
+                slot: 12000,
+            },
+        ]
+    );
+    assert!(plan
+        .migrate_owned_slots(&nodes[0], &nodes[2], &[10000, 10001])
+        .is_err());
+    assert!(plan.migrate_slots_to(&nodes[1..], &nodes[2], &[1]).is_err());
+}
+
+#[test]
+fn test_run_parallel() {
+    let mv = |src: &str, dst: &str, slot| Step::MigrateSlot {
+        src: src.to_string(),
+        dst: dst.to_string(),
+        slot,
+    };
+    let steps = vec![
+        mv("a", "b", 1),
+        mv("c", "d", 2),
+        mv("a", "b", 3),
+        mv("b", "e", 4),
+        mv("f", "g", 5),
+        mv("h", "i", 6),
+    ];
+    let pairs = pairs(&steps, 0..steps.len());
+    assert_eq!(pairs.len(), 5);
+    assert_eq!(pairs[0].steps, vec![0, 2]);
+
+    use std::sync::Mutex;
+    let busy = Mutex::new(HashSet::new());
+    let peak = Mutex::new(0);
+    let done = Mutex::new(Vec::new());
+    run_parallel(&pairs, 3, |pair| {
+        {
+            let mut busy = busy.lock().unwrap();
+            assert!(busy.insert(pair.src.to_string()) && busy.insert(pair.dst.to_string()));
+            let mut peak = peak.lock().unwrap();
+            *peak = (*peak).max(busy.len() / 2);
+        }
+        thread::sleep(time::Duration::from_millis(20));
+        let mut busy = busy.lock().unwrap();
+        busy.remove(pair.src);
+        busy.remove(pair.dst);
+        done.lock().unwrap().push(pair.src.to_string());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(*peak.lock().unwrap(), 3);
+    assert_eq!(done.lock().unwrap().len(), 5);
+
+    let err = run_parallel(&pairs, 2, |pair| {
+        if pair.src == "a" {
+            Err(Error::Precondition("boom".to_string()))
+        } else {
+            Ok(())
+        }
+    });
+    assert!(err.is_err());
+}
+
+// FAILOVER_TIMEOUT bound the wait for a replica to take over its master.
+const FAILOVER_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+const CLUSTER_SLOTS: usize = 16384;
+
+// Step is a single change to the cluster, nodes are referred by address.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Step {
+    // AddSlots assign inclusive slot ranges to node.
+    AddSlots {
+        node: String,
+        slots: Vec<(usize, usize)>,
+    },
+    SetConfigEpoch {
+        node: String,
+        epoch: u64,
+    },
+    Meet {
+        node: String,
+        peer: String,
+    },
+    // WaitConsistent block until the views of all nodes agree.
+    WaitConsistent {
+        nodes: Vec<String>,
+    },
+    Replicate {
+        node: String,
+        master: String,
+    },
+    // Failover promote the replica node in place of its master.
+    Failover {
+        node: String,
+    },
+    // MigrateSlot is a whole slot move, the same as set_slot importing on dst,
+    // set_slot migrating on src, migrate_keys and set_slot node on both.
+    MigrateSlot {
+        src: String,
+        dst: String,
+        slot: usize,
+    },
+    // SetSlot is CLUSTER SETSLOT on node, peer is the node the slot is
+    // imported from, migrated to or assigned to, none for stable.
+    SetSlot {
+        node: String,
+        slot: usize,
+        state: SlotState,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer: Option<String>,
+    },
+    MigrateKeys {
+        src: String,
+        dst: String,
+        slot: usize,
+    },
+    // Forget is sent by node about target, target may be gone so it is named by id.
+    Forget {
+        node: String,
+        target: String,
+        id: String,
+    },
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::AddSlots { node, slots } => {
+                let slots: Vec<String> = slots
+                    .iter()
+                    .map(|(start, end)| {
+                        if start == end {
+                            start.to_string()
+                        } else {
+                            format!("{}-{}", start, end)
+                        }
+                    })
+                    .collect();
+                write!(f, "add slots {} to {}", slots.join(","), node)
+            }
+            Step::SetConfigEpoch { node, epoch } => {
+                write!(f, "set config epoch of {} to {}", node, epoch)
+            }
+            Step::Meet { node, peer } => write!(f, "{} meet {}", node, peer),
+            Step::WaitConsistent { nodes } => {
+                write!(f, "wait until {} agree", nodes.join(","))
+            }
+            Step::Replicate { node, master } => write!(f, "{} replicate {}", node, master),
+            Step::Failover { node } => write!(f, "failover {} to master", node),
+            Step::MigrateSlot { src, dst, slot } => {
+                write!(f, "migrate slot {} from {} to {}", slot, src, dst)
+            }
+            Step::SetSlot {
+                node,
+                slot,
+                state,
+                peer,
+            } => {
+                write!(f, "set slot {} {} on {}", slot, state.name(), node)?;
+                match peer {
+                    Some(peer) => write!(f, " with {}", peer),
+                    None => Ok(()),
+                }
+            }
+            Step::MigrateKeys { src, dst, slot } => {
+                write!(f, "migrate keys of slot {} from {} to {}", slot, src, dst)
+            }
+            Step::Forget { node, target, id } => {
+                write!(f, "{} forget {} ({})", node, target, id)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlotState {
+    Importing,
+    Migrating,
+    Node,
+    Stable,
+}
+
+impl SlotState {
+    fn name(self) -> &'static str {
+        match self {
+            SlotState::Importing => "importing",
+            SlotState::Migrating => "migrating",
+            SlotState::Node => "node",
+            SlotState::Stable => "stable",
+        }
+    }
+}
+
+// myself return the entry of the node which view is topology.
+fn myself<'a>(topology: &'a Topology, addr: &str) -> AsResult<&'a NodeEntry> {
+    topology
+        .myself()
+        .ok_or_else(|| Error::Precondition(format!("{} has no myself entry", addr)))
+}
+
+// slot_owners is the id of the owner of every slot in topology, None when the
+// slot is not covered.
+fn slot_owners(topology: &Topology) -> Vec<Option<String>> {
+    let mut owners = vec![None; CLUSTER_SLOTS];
+    for node in &topology.nodes {
+        for slot in &node.slots {
+            owners[*slot] = Some(node.id.clone());
+        }
+    }
+    owners
+}
+
+impl Step {
+    // check compare the cluster with the state the step expect, view return
+    // the topology seen by the node at an address. It return false when the
+    // step is already applied and error when the cluster has drifted.
+    pub fn check(&self, view: &mut dyn FnMut(&str) -> AsResult<Topology>) -> AsResult<bool> {
+        let drift = |msg: String| Err(Error::Precondition(msg));
+        match self {
+            Step::AddSlots { node, slots } => {
+                let topology = view(node)?;
+                let me = myself(&topology, node)?;
+                let mut done = true;
+                for slot in slots.iter().flat_map(|(start, end)| *start..=*end) {
+                    match topology.masters().find(|n| n.slots.contains(&slot)) {
+                        Some(owner) if owner.id != me.id => {
+                            return drift(format!("slot {} is owned by {}", slot, owner.addr))
+                        }
+                        Some(_) => {}
+                        None => done = false,
+                    }
+                }
+                Ok(!done)
+            }
+            Step::SetConfigEpoch { node, epoch } => {
+                let topology = view(node)?;
+                if myself(&topology, node)?.config_epoch == *epoch {
+                    return Ok(false);
+                }
+                if topology.nodes.len() > 1 {
+                    return drift(format!("{} already joined a cluster", node));
+                }
+                Ok(true)
+            }
+            Step::Meet { node, peer } => {
+                let topology = view(node)?;
+                Ok(!topology.nodes.iter().any(|n| n.addr == *peer))
+            }
+            Step::WaitConsistent { nodes } => {
+                // consistent when every view covers all slots with the same owners.
+                let mut first: Option<Vec<Option<String>>> = None;
+                for node in nodes {
+                    let owners = slot_owners(&view(node)?);
+                    if owners.iter().any(|owner| owner.is_none()) {
+                        return Ok(true);
+                    }
+                    match &first {
+                        Some(first) if *first != owners => return Ok(true),
+                        Some(_) => {}
+                        None => first = Some(owners),
+                    }
+                }
+                Ok(false)
+            }
+            Step::Replicate { node, master } => {
+                let master_id = myself(&view(master)?, master)?.id.clone();
+                let topology = view(node)?;
+                let me = myself(&topology, node)?;
+                if me.master.as_ref() == Some(&master_id) {
+                    return Ok(false);
+                }
+                if !me.slots.is_empty() {
+                    return drift(format!("{} still owns {} slots", node, me.slots.len()));
+                }
+                if topology.node(&master_id).is_none() {
+                    return drift(format!("{} does not know {}", node, master));
+                }
+                Ok(true)
+            }
+            Step::Failover { node } => {
+                let topology = view(node)?;
+                let me = myself(&topology, node)?;
+                if me.is_master() {
+                    return Ok(false);
+                }
+                if me.master.is_none() {
+                    return drift(format!("{} has no master", node));
+                }
+                Ok(true)
+            }
+            Step::MigrateSlot { src, dst, slot } => {
+                let topology = view(src)?;
+                if myself(&topology, src)?.slots.contains(slot) {
+                    return Ok(true);
+                }
+                let dst_view = view(dst)?;
+                if myself(&dst_view, dst)?.slots.contains(slot) {
+                    return Ok(false);
+                }
+                drift(format!(
+                    "slot {} is owned by neither {} nor {}",
+                    slot, src, dst
+                ))
+            }
+            Step::SetSlot {
+                node,
+                slot,
+                state,
+                peer,
+            } => {
+                let peer_id = match peer {
+                    Some(peer) => Some(myself(&view(peer)?, peer)?.id.clone()),
+                    None => None,
+                };
+                let topology = view(node)?;
+                let me = myself(&topology, node)?;
+                let owned = me.slots.contains(slot);
+                let open = me.importing.contains_key(slot) || me.migrating.contains_key(slot);
+                let done = |open: &HashMap<usize, String>| {
+                    peer_id.is_some() && open.get(slot) == peer_id.as_ref()
+                };
+                match state {
+                    SlotState::Importing if done(&me.importing) => Ok(false),
+                    SlotState::Migrating if done(&me.migrating) => Ok(false),
+                    SlotState::Importing if owned => {
+                        drift(format!("{} already owns slot {}", node, slot))
+                    }
+                    SlotState::Migrating if !owned => {
+                        drift(format!("{} does not own slot {}", node, slot))
+                    }
+                    SlotState::Node if !open => {
+                        let owner = topology.nodes.iter().find(|n| n.slots.contains(slot));
+                        Ok(owner.map(|n| &n.id) != peer_id.as_ref())
+                    }
+                    SlotState::Stable => Ok(open),
+                    _ => Ok(true),
+                }
+            }
+            Step::MigrateKeys { src, dst, slot } => {
+                let dst_id = myself(&view(dst)?, dst)?.id.clone();
+                let topology = view(src)?;
+                if myself(&topology, src)?.migrating.get(slot) != Some(&dst_id) {
+                    return drift(format!(
+                        "slot {} is not migrating from {} to {}",
+                        slot, src, dst
+                    ));
+                }
+                Ok(true)
+            }
+            Step::Forget { node, id, .. } => {
+                let topology = view(node)?;
+                if myself(&topology, node)?.id == *id {
+                    return drift(format!("{} can't forget itself", node));
+                }
+                Ok(topology.node(id).is_some())
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PlanFormat {
+    Human,
+    Json,
+    Yaml,
+}
+
+impl PlanFormat {
+    // from_path pick yaml for .yaml and .yml files, json otherwise.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> PlanFormat {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => PlanFormat::Yaml,
+            _ => PlanFormat::Json,
+        }
+    }
+}
+
+impl FromStr for PlanFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PlanFormat, Error> {
+        match s {
+            "human" => Ok(PlanFormat::Human),
+            "json" => Ok(PlanFormat::Json),
+            "yaml" => Ok(PlanFormat::Yaml),
+            other => Err(Error::InvalidArg(format!("unknown plan format {}", other))),
+        }
+    }
+}
+
+// Plan is the ordered list of steps a command would run, it is computed from
+// the current cluster state without changing it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    pub steps: Vec<Step>,
+}
+
+impl Plan {
+    pub fn parse(text: &str, format: PlanFormat) -> AsResult<Plan> {
+        match format {
+            PlanFormat::Yaml => {
+                serde_yaml::from_str(text).map_err(|err| Error::Parse(err.to_string()))
+            }
+            _ => serde_json::from_str(text).map_err(|err| Error::Parse(err.to_string())),
+        }
+    }
+
+    // from_file read a plan saved by --save-plan.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> AsResult<Plan> {
+        let path = path.as_ref();
+        let text =
+            fs::read_to_string(path).map_err(|err| Error::Io(path.display().to_string(), err))?;
+        Plan::parse(&text, PlanFormat::from_path(path))
+    }
+
+    pub fn push(&mut self, step: Step) {
+        self.steps.push(step);
+    }
+
+    pub fn migrate_slots(&mut self, src: &Node, dst: &Node, slots: &[usize]) {
+        for slot in slots {
+            self.push(Step::MigrateSlot {
+                src: src.addr(),
+                dst: dst.addr(),
+                slot: *slot,
+            });
+        }
+    }
+
+    // migrate_owned_slots add the moves of slots after checking src owns all of them.
+    pub fn migrate_owned_slots(&mut self, src: &Node, dst: &Node, slots: &[usize]) -> AsResult<()> {
+        let owned = src.slots();
+        let missing: Vec<usize> = slots
+            .iter()
+            .filter(|slot| !owned.contains(slot))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::Precondition(format!(
+                "{} does not own slots {}",
+                src.addr(),
+                slots::format(&missing)
+            )));
+        }
+        self.migrate_slots(src, dst, slots);
+        Ok(())
+    }
+
+    // migrate_slots_to add the moves of slots from their owner among nodes to
+    // dst, slots already on dst are left out.
+    pub fn migrate_slots_to(
+        &mut self,
+        nodes: &[Node],
+        dst: &Node,
+        slots: &[usize],
+    ) -> AsResult<()> {
+        for slot in slots {
+            let owner = nodes
+                .iter()
+                .find(|n| n.is_master() && n.slots().contains(slot))
+                .ok_or_else(|| Error::Precondition(format!("slot {} has no owner", slot)))?;
+            if owner.name != dst.name {
+                self.migrate_slots(owner, dst, &[*slot]);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W, format: PlanFormat) -> io::Result<()> {
+        match format {
+            PlanFormat::Human => {
+                for (idx, step) in self.steps.iter().enumerate() {
+                    writeln!(w, "{}. {}", idx + 1, step)?;
+                }
+                writeln!(w, "{} steps, nothing was changed", self.steps.len())
+            }
+            PlanFormat::Json => {
+                serde_json::to_writer_pretty(&mut *w, self)?;
+                writeln!(w)
+            }
+            PlanFormat::Yaml => serde_yaml::to_writer(&mut *w, self)
+                .map_err(|err| io::Error::other(err.to_string())),
+        }
+    }
+
+    // execute run the steps in order and stop at the first error. Every step
+    // is checked against the cluster first, applied steps are skipped. Slot
+    // moves are recorded in the journal at journal_path until they are done,
+    // up to parallel moves between disjoint pairs of nodes run at once.
+    pub fn execute<P: AsRef<Path>>(&self, journal_path: P, parallel: usize) -> AsResult<()> {
+        let moves: Vec<(String, String, usize)> = self
+            .steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::MigrateSlot { src, dst, slot } => Some((src.clone(), dst.clone(), *slot)),
+                _ => None,
+            })
+            .collect();
+        let journal = if moves.is_empty() {
+            None
+        } else {
+            let journal = Journal::create(journal_path, &moves)?;
+            progress::start(moves.len(), count_keys(&moves)?)?;
+            Some(journal)
+        };
+        let result = self.run_steps(journal.as_ref(), parallel.max(1));
+        progress::finish();
+        match journal {
+            Some(journal) if result.is_ok() || !journal.is_started() => {
+                journal.finish()?;
+                result
+            }
+            _ => result,
+        }
+    }
+
+    fn run_steps(&self, journal: Option<&Journal>, parallel: usize) -> AsResult<()> {
+        let mut nodes = HashMap::new();
+        let mut idx = 0;
+        while idx < self.steps.len() {
+            let moves = self.steps[idx..]
+                .iter()
+                .take_while(|step| matches!(step, Step::MigrateSlot { .. }))
+                .count();
+            if parallel > 1 && moves > 1 {
+                let pairs = pairs(&self.steps, idx..idx + moves);
+                run_parallel(&pairs, parallel, |pair| {
+                    // every worker has its own connections.
+                    let mut nodes = HashMap::new();
+                    for idx in &pair.steps {
+                        run_step(&mut nodes, *idx, &self.steps[*idx], journal)?;
+                    }
+                    Ok(())
+                })?;
+                idx += moves;
+                continue;
+            }
+            run_step(&mut nodes, idx, &self.steps[idx], journal)?;
+            idx += 1;
+        }
+        Ok(())
+    }
+}
+
+fn run_step(
+    nodes: &mut HashMap<String, Node>,
+    idx: usize,
+    step: &Step,
+    journal: Option<&Journal>,
+) -> AsResult<()> {
+    let run = step
+        .check(&mut |addr| cached_node(nodes, addr)?.topology())
+        .map_err(|err| match err {
+            Error::Precondition(msg) => {
+                Error::Precondition(format!("step {} ({}): {}", idx + 1, step, msg))
+            }
+            err => err,
+        })?;
+    if !run {
+        println!("skip applied step {}: {}", idx + 1, step);
+        if let (Step::MigrateSlot { src, dst, slot }, Some(journal)) = (step, journal) {
+            journal.record(Event::Done, src, dst, *slot)?;
+            progress::slot_done()?;
+        }
+        return Ok(());
+    }
+    if let Step::MigrateSlot { .. } = step {
+        // migrate_slot print the progress itself.
+    } else {
+        println!("{}", step);
+    }
+    match (step, journal) {
+        (Step::MigrateSlot { src, dst, slot }, Some(journal)) => {
+            journal.record(Event::Started, src, dst, *slot)?;
+            execute_step(nodes, step)?;
+            journal.record(Event::Done, src, dst, *slot)?;
+            progress::slot_done()
+        }
+        _ => execute_step(nodes, step),
+    }
+}
+
+// Pair is the slot moves from src to dst, steps are indexes in the plan.
+#[derive(Debug, PartialEq)]
+struct Pair<'a> {
+    src: &'a str,
+    dst: &'a str,
+    steps: Vec<usize>,
+}
+
+// pairs group the migrate_slot steps in range by source and destination,
+// keeping the order of the plan.
+fn pairs(steps: &[Step], range: Range<usize>) -> Vec<Pair<'_>> {
+    let mut pairs: Vec<Pair> = Vec::new();
+    for idx in range {
+        if let Step::MigrateSlot { src, dst, .. } = &steps[idx] {
+            match pairs.iter_mut().find(|p| p.src == src && p.dst == dst) {
+                Some(pair) => pair.steps.push(idx),
+                None => pairs.push(Pair {
+                    src,
+                    dst,
+                    steps: vec![idx],
+                }),
+            }
+        }
+    }
+    pairs
+}
+
+// run_parallel run work for every pair on up to parallel threads, a node is
+// never part of two running pairs. It stop starting pairs after the first
+// error and return it once the running ones are over.
+fn run_parallel<F>(pairs: &[Pair], parallel: usize, work: F) -> AsResult<()>
+where
+    F: Fn(&Pair) -> AsResult<()> + Sync,
+{
+    let (tx, rx) = mpsc::channel();
+    let work = &work;
+    thread::scope(|scope| {
+        let mut started = vec![false; pairs.len()];
+        let mut busy: HashSet<&str> = HashSet::new();
+        let mut running = 0;
+        let mut first_err = None;
+        loop {
+            for (idx, pair) in pairs.iter().enumerate() {
+                if first_err.is_some() || running >= parallel {
+                    break;
+                }
+                if started[idx] || busy.contains(pair.src) || busy.contains(pair.dst) {
+                    continue;
+                }
+                started[idx] = true;
+                busy.insert(pair.src);
+                busy.insert(pair.dst);
+                running += 1;
+                let tx = tx.clone();
+                scope.spawn(move || tx.send((idx, work(pair))));
+            }
+            if running == 0 {
+                break;
+            }
+            let (idx, result) = rx.recv().unwrap();
+            running -= 1;
+            busy.remove(pairs[idx].src);
+            busy.remove(pairs[idx].dst);
+            if let Err(err) = result {
+                first_err.get_or_insert(err);
+            }
+        }
+        first_err.map_or(Ok(()), Err)
+    })
+}
+
+// count_keys sum the keys in the slots to move, one round trip per source.
+fn count_keys(moves: &[(String, String, usize)]) -> AsResult<u64> {
+    let mut by_src: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (src, _, slot) in moves {
+        by_src.entry(src).or_default().push(*slot);
+    }
+    let mut nodes = HashMap::new();
+    let mut total = 0;
+    for (src, slots) in by_src {
+        let counts = cached_node(&mut nodes, src)?.countkeysinslots(&slots)?;
+        total += counts.iter().sum::<usize>() as u64;
+    }
+    Ok(total)
+}
+
+// cached_node return the connected node at addr, the connection is kept in
+// nodes for the following steps.
+pub fn cached_node<'a>(nodes: &'a mut HashMap<String, Node>, addr: &str) -> AsResult<&'a Node> {
+    if !nodes.contains_key(addr) {
+        let mut node = Node::new(addr.as_bytes())?;
+        node.connect()?;
+        nodes.insert(addr.to_string(), node);
+    }
+    Ok(&nodes[addr])
+}
+
+fn execute_step(nodes: &mut HashMap<String, Node>, step: &Step) -> AsResult<()> {
+    match step {
+        Step::AddSlots { node: addr, slots } => {
+            let slots: Vec<usize> = slots
+                .iter()
+                .flat_map(|(start, end)| *start..=*end)
+                .collect();
+            cached_node(nodes, addr)?.add_slots(&slots)
+        }
+        Step::SetConfigEpoch { node: addr, epoch } => {
+            cached_node(nodes, addr)?.set_config_epoch(*epoch as usize)
+        }
+        Step::Meet { node: addr, peer } => {
+            let (ip, port) = topology::split_addr(peer).ok_or(Error::BadAddr)?;
+            cached_node(nodes, addr)?.meet(&ip, &port)
+        }
+        Step::WaitConsistent { nodes: addrs } => {
+            let members = addrs
+                .iter()
+                .map(|addr| Node::new(addr.as_bytes()))
+                .collect::<AsResult<Vec<Node>>>()?;
+            let cluster = Cluster::new(members);
+            while !cluster.consistency()? {
+                thread::sleep(time::Duration::from_secs(1));
+            }
+            Ok(())
+        }
+        Step::Replicate { node: addr, master } => {
+            let master_id = cached_node(nodes, master)?.name.clone();
+            let mut slave = cached_node(nodes, addr)?.clone();
+            slave.slaveof = Some(master_id);
+            slave.set_slave()
+        }
+        Step::Failover { node: addr } => {
+            let node = cached_node(nodes, addr)?;
+            node.failover()?;
+            let deadline = time::Instant::now() + FAILOVER_TIMEOUT;
+            while !node.topology()?.myself().is_some_and(|n| n.is_master()) {
+                if time::Instant::now() > deadline {
+                    return Err(Error::Timeout(addr.clone()));
+                }
+                thread::sleep(time::Duration::from_millis(100));
+            }
+            Ok(())
+        }
+        Step::MigrateSlot { src, dst, slot } => {
+            cached_node(nodes, src)?;
+            cached_node(nodes, dst)?;
+            cluster::migrate_slot(&nodes[src], &nodes[dst], *slot)
+        }
+        Step::SetSlot {
+            node: addr,
+            slot,
+            state,
+            peer,
+        } => {
+            let peer_id = match peer {
+                Some(peer) => cached_node(nodes, peer)?.name.clone(),
+                None => String::new(),
+            };
+            let target = cached_node(nodes, addr)?;
+            match state {
+                SlotState::Stable => target.setslot_stable(*slot),
+                _ if peer.is_none() => Err(Error::InvalidArg(format!(
+                    "set slot {} {} need a peer",
+                    slot,
+                    state.name()
+                ))),
+                _ => target.setslot(&state.name().to_uppercase(), peer_id, *slot),
+            }
+        }
+        Step::MigrateKeys { src, dst, slot } => {
+            cached_node(nodes, src)?;
+            cached_node(nodes, dst)?;
+            cluster::migrate_keys(&nodes[src], &nodes[dst], *slot)
+        }
+        Step::Forget {
+            node: addr,
+            target,
+            id,
+        } => {
+            let mut gone = Node::new(target.as_bytes())?;
+            gone.name = id.clone();
+            cached_node(nodes, addr)?.forget(&gone)
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use cluster::{AsResult, Error, Node};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+#[cfg(test)]
+fn fresh(addr: &str) -> Facts {
+    Facts {
+        addr: addr.to_string(),
+        cluster_enabled: true,
+        keys: 0,
+        slots: 0,
+        known_nodes: 1,
+        version: "7.2.4".to_string(),
+        node_timeout: Some(15000),
+        bus_port: Some(17000),
+        bus_error: None,
+    }
+}
+
+#[test]
+fn test_validate() {
+    let facts = vec![fresh("127.0.0.1:7000"), fresh("127.0.0.1:7001")];
+    let report = validate(&facts, None);
+    assert_eq!(report.count(Level::Fail), 0);
+    assert_eq!(report.count(Level::Warn), 0);
+    assert!(report.result().is_ok());
+
+    let mut bad = fresh("127.0.0.1:7002");
+    bad.keys = 3;
+    bad.known_nodes = 4;
+    bad.slots = 100;
+    bad.bus_error = Some("connection refused".to_string());
+    bad.version = "6.2.14".to_string();
+    bad.node_timeout = Some(500);
+    let mut disabled = fresh("127.0.0.1:7003");
+    disabled.cluster_enabled = false;
+    let report = validate(
+        &[
+            fresh("127.0.0.1:7000"),
+            fresh("127.0.0.1:7001"),
+            bad,
+            disabled,
+        ],
+        None,
+    );
+    assert_eq!(report.count(Level::Fail), 5);
+    assert_eq!(report.count(Level::Warn), 2);
+    assert_eq!(report.failed_nodes(), 2);
+    assert!(report.result().is_err());
+    let text = report.to_string();
+    assert!(text.contains("  FAIL keys: holds 3 keys, flush it or use an empty node\n"));
+    assert!(text.contains("  warn version: 6.2.14 differs from 7.2.4\n"));
+    assert!(text.contains(
+        "  warn node timeout: 500ms is too short, failovers may flap on a slow network\n"
+    ));
+    assert!(text.contains("  FAIL cluster bus: port 17000 unreachable: connection refused\n"));
+    assert!(text.ends_with("pre-flight: 4 nodes, 2 failed, 2 warnings\n"));
+
+    // a node added to a cluster is compared with the cluster.
+    let mut reference = fresh("127.0.0.1:7000");
+    reference.version = "7.0.15".to_string();
+    let report = validate(&facts, Some(&reference));
+    assert_eq!(report.count(Level::Warn), 2);
+    assert!(report.result().is_ok());
+}
+
+const BUS_PORT_OFFSET: u16 = 10000;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// MIN_NODE_TIMEOUT and MAX_NODE_TIMEOUT bound a sensible cluster-node-timeout
+// in ms, shorter fails over on network hiccups, longer detects failures late.
+const MIN_NODE_TIMEOUT: u64 = 1000;
+const MAX_NODE_TIMEOUT: u64 = 60000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Ok,
+    // Warn is reported but doesn't stop create or add.
+    Warn,
+    Fail,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Ok => "ok",
+            Level::Warn => "warn",
+            Level::Fail => "FAIL",
+        }
+    }
+}
+
+// Check is the result of one check on a node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub addr: String,
+    pub name: &'static str,
+    pub level: Level,
+    pub detail: String,
+}
+
+// Facts is what the checks need to know about a node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Facts {
+    pub addr: String,
+    pub cluster_enabled: bool,
+    pub keys: u64,
+    pub slots: usize,
+    pub known_nodes: usize,
+    pub version: String,
+    // node_timeout is the cluster-node-timeout in ms.
+    pub node_timeout: Option<u64>,
+    // bus_port is the announced cluster bus port or port+10000, bus_error
+    // why it can't be reached.
+    pub bus_port: Option<u16>,
+    pub bus_error: Option<String>,
+}
+
+impl Facts {
+    // gather query node and try to open its cluster bus port.
+    pub fn gather(node: &Node) -> AsResult<Facts> {
+        let info = node.server_info()?;
+        let port: u16 = node.port.parse().map_err(|_| Error::BadAddr)?;
+        let mut facts = Facts {
+            addr: node.addr(),
+            cluster_enabled: info.get("cluster_enabled").map(|x| x.as_str()) == Some("1"),
+            keys: node.dbsize()?,
+            slots: 0,
+            known_nodes: 0,
+            version: info.get("redis_version").cloned().unwrap_or_default(),
+            node_timeout: None,
+            bus_port: port.checked_add(BUS_PORT_OFFSET),
+            bus_error: None,
+        };
+        if !facts.cluster_enabled {
+            return Ok(facts);
+        }
+        facts.known_nodes = node
+            .info()?
+            .get("cluster_known_nodes")
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(0);
+        if let Some(me) = node.topology()?.myself() {
+            facts.slots = me.slots.len();
+            facts.bus_port = me.cport.or(facts.bus_port);
+        }
+        facts.node_timeout = node
+            .config_get("cluster-node-timeout")?
+            .and_then(|timeout| timeout.parse().ok());
+        facts.bus_error = match facts.bus_port {
+            Some(bus_port) => probe(&node.ip, bus_port).err(),
+            None => Some(format!("port {} has no bus port", port)),
+        };
+        Ok(facts)
+    }
+}
+
+// probe open a tcp connection to host:port.
+fn probe(host: &str, port: u16) -> Result<(), String> {
+    let addr = (host, port)
+        .to_socket_addrs()
+        .map_err(|err| err.to_string())?
+        .next()
+        .ok_or_else(|| format!("{} has no address", host))?;
+    TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+// Report is the checks of all nodes in order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn push(&mut self, facts: &Facts, name: &'static str, level: Level, detail: String) {
+        self.checks.push(Check {
+            addr: facts.addr.clone(),
+            name,
+            level,
+            detail,
+        });
+    }
+
+    pub fn count(&self, level: Level) -> usize {
+        self.checks.iter().filter(|c| c.level == level).count()
+    }
+
+    pub fn failed_nodes(&self) -> usize {
+        let mut nodes: Vec<&str> = self
+            .checks
+            .iter()
+            .filter(|c| c.level == Level::Fail)
+            .map(|c| c.addr.as_str())
+            .collect();
+        nodes.dedup();
+        nodes.len()
+    }
+
+    // result fail when a node is not fit to join a cluster.
+    pub fn result(&self) -> AsResult<()> {
+        match self.failed_nodes() {
+            0 => Ok(()),
+            n => Err(Error::Precondition(format!(
+                "{} nodes failed the pre-flight checks",
+                n
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut nodes = 0;
+        for (idx, check) in self.checks.iter().enumerate() {
+            if idx == 0 || self.checks[idx - 1].addr != check.addr {
+                writeln!(f, "node {}", check.addr)?;
+                nodes += 1;
+            }
+            writeln!(
+                f,
+                "  {:<4} {}: {}",
+                check.level.name(),
+                check.name,
+                check.detail
+            )?;
+        }
+        writeln!(
+            f,
+            "pre-flight: {} nodes, {} failed, {} warnings",
+            nodes,
+            self.failed_nodes(),
+            self.count(Level::Warn)
+        )
+    }
+}
+
+// common is the most common value, the first one seen on a tie.
+fn common<T: Clone + Eq + ::std::hash::Hash>(values: &[T]) -> Option<T> {
+    let mut counts: HashMap<&T, usize> = HashMap::new();
+    for value in values {
+        *counts.entry(value).or_insert(0) += 1;
+    }
+    let max = counts.values().cloned().max()?;
+    values.iter().find(|v| counts[v] == max).cloned()
+}
+
+// validate check the nodes are fit to join a cluster. Versions and timeouts
+// are compared with reference, a node of the cluster joined, or else with
+// the most common ones.
+pub fn validate(facts: &[Facts], reference: Option<&Facts>) -> Report {
+    let enabled: Vec<&Facts> = facts.iter().filter(|f| f.cluster_enabled).collect();
+    let version = reference
+        .map(|r| r.version.clone())
+        .or_else(|| common(&facts.iter().map(|f| f.version.clone()).collect::<Vec<_>>()));
+    let timeout = reference.and_then(|r| r.node_timeout).or_else(|| {
+        common(
+            &enabled
+                .iter()
+                .filter_map(|f| f.node_timeout)
+                .collect::<Vec<_>>(),
+        )
+    });
+    let mut report = Report::default();
+    for facts in facts {
+        if !facts.cluster_enabled {
+            report.push(
+                facts,
+                "cluster enabled",
+                Level::Fail,
+                "cluster support is disabled, set cluster-enabled yes".to_string(),
+            );
+        } else {
+            report.push(facts, "cluster enabled", Level::Ok, "yes".to_string());
+            if facts.known_nodes > 1 {
+                report.push(
+                    facts,
+                    "known nodes",
+                    Level::Fail,
+                    format!(
+                        "already knows {} nodes, reset it with CLUSTER RESET",
+                        facts.known_nodes
+                    ),
+                );
+            }
+            if facts.slots > 0 {
+                report.push(
+                    facts,
+                    "slots",
+                    Level::Fail,
+                    format!("already owns {} slots", facts.slots),
+                );
+            }
+        }
+        if facts.keys > 0 {
+            report.push(
+                facts,
+                "keys",
+                Level::Fail,
+                format!("holds {} keys, flush it or use an empty node", facts.keys),
+            );
+        } else {
+            report.push(facts, "keys", Level::Ok, "empty".to_string());
+        }
+        if facts.cluster_enabled {
+            let port = facts.bus_port.map_or("-".to_string(), |p| p.to_string());
+            match &facts.bus_error {
+                Some(err) => report.push(
+                    facts,
+                    "cluster bus",
+                    Level::Fail,
+                    format!("port {} unreachable: {}", port, err),
+                ),
+                None => report.push(
+                    facts,
+                    "cluster bus",
+                    Level::Ok,
+                    format!("port {} reachable", port),
+                ),
+            }
+        }
+        match &version {
+            Some(version) if *version != facts.version => report.push(
+                facts,
+                "version",
+                Level::Warn,
+                format!("{} differs from {}", facts.version, version),
+            ),
+            _ => report.push(facts, "version", Level::Ok, facts.version.clone()),
+        }
+        if !facts.cluster_enabled {
+            continue;
+        }
+        let (level, detail) = match facts.node_timeout {
+            None => (Level::Warn, "unknown".to_string()),
+            Some(ms) if ms < MIN_NODE_TIMEOUT => (
+                Level::Warn,
+                format!(
+                    "{}ms is too short, failovers may flap on a slow network",
+                    ms
+                ),
+            ),
+            Some(ms) if ms > MAX_NODE_TIMEOUT => (
+                Level::Warn,
+                format!("{}ms is too long, failures are detected late", ms),
+            ),
+            Some(ms) if timeout.is_some_and(|t| t != ms) => (
+                Level::Warn,
+                format!("{}ms differs from {}ms", ms, timeout.unwrap()),
+            ),
+            Some(ms) => (Level::Ok, format!("{}ms", ms)),
+        };
+        report.push(facts, "node timeout", level, detail);
+    }
+    report
+}
+
+// run check nodes before they join a cluster, reference is a node of the
+// cluster they join. A node which can't be queried fails the checks.
+pub fn run(nodes: &[Node], reference: Option<&Node>) -> AsResult<Report> {
+    let reference = match reference {
+        Some(node) => Some(Facts::gather(node)?),
+        None => None,
+    };
+    let mut facts = Vec::new();
+    let mut unreachable = Report::default();
+    for node in nodes {
+        match Facts::gather(node) {
+            Ok(f) => facts.push(f),
+            Err(err) => unreachable.checks.push(Check {
+                addr: node.addr(),
+                name: "query",
+                level: Level::Fail,
+                detail: err.to_string(),
+            }),
+        }
+    }
+    let mut report = validate(&facts, reference.as_ref());
+    report.checks.extend(unreachable.checks);
+    Ok(report)
+}
@@ -0,0 +1,227 @@
+use cluster::{AsResult, Error};
+use serde_json;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_snapshot() {
+    let now = Instant::now();
+    let mut tracker = Tracker::new(10, 5000, now);
+    tracker.keys_moved = 1000;
+    tracker.bytes_moved = 3 * 1024 * 1024 / 2;
+    tracker.slots_done = 2;
+    let snapshot = tracker.snapshot(now + Duration::from_secs(5));
+    assert_eq!(snapshot.keys_per_sec, 200);
+    assert_eq!(snapshot.eta_secs, Some(20));
+    assert_eq!(
+        snapshot.to_string(),
+        "progress: slots 2/10, keys 1000/5000, 1.5MB, 200 keys/s, eta 20s"
+    );
+    assert_eq!(
+        serde_json::to_string(&snapshot).unwrap(),
+        r#"{"slots_done":2,"slots_total":10,"keys_moved":1000,"keys_total":5000,"bytes_moved":1572864,"keys_per_sec":200,"elapsed_secs":5,"eta_secs":20}"#
+    );
+    // the current rate is measured since the last report.
+    tracker.keys_moved = 1100;
+    let snapshot = tracker.snapshot(now + Duration::from_secs(6));
+    assert_eq!(snapshot.keys_per_sec, 100);
+
+    // empty slots are estimated by slot.
+    let mut tracker = Tracker::new(4, 0, now);
+    assert_eq!(tracker.snapshot(now).eta_secs, None);
+    tracker.slots_done = 1;
+    let snapshot = tracker.snapshot(now + Duration::from_secs(2));
+    assert_eq!(snapshot.eta_secs, Some(6));
+    assert_eq!(
+        snapshot.to_string(),
+        "progress: slots 1/4, keys 0/0, 0 keys/s, eta 6s"
+    );
+}
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+// FILE is the file the progress is appended to as json lines.
+static FILE: RwLock<Option<String>> = RwLock::new(None);
+
+// TRACKER follow the slot moves of the running plan, key moves are counted
+// from any worker.
+static TRACKER: Mutex<Option<Tracker>> = Mutex::new(None);
+
+pub fn set_file(path: Option<String>) {
+    *FILE.write().unwrap() = path;
+}
+
+// Snapshot is one progress report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Snapshot {
+    pub slots_done: usize,
+    pub slots_total: usize,
+    pub keys_moved: u64,
+    pub keys_total: u64,
+    // bytes_moved is only counted when key sizes are measured for
+    // --bytes-per-sec or --large-key.
+    pub bytes_moved: u64,
+    pub keys_per_sec: u64,
+    pub elapsed_secs: u64,
+    pub eta_secs: Option<u64>,
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "progress: slots {}/{}, keys {}/{}",
+            self.slots_done, self.slots_total, self.keys_moved, self.keys_total
+        )?;
+        if self.bytes_moved > 0 {
+            write!(f, ", {}", human_bytes(self.bytes_moved))?;
+        }
+        write!(f, ", {} keys/s", self.keys_per_sec)?;
+        match self.eta_secs {
+            Some(eta) => write!(f, ", eta {}s", eta),
+            None => Ok(()),
+        }
+    }
+}
+
+fn human_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < units.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", value, units[unit])
+    }
+}
+
+struct Tracker {
+    start: Instant,
+    slots_total: usize,
+    slots_done: usize,
+    keys_total: u64,
+    keys_moved: u64,
+    bytes_moved: u64,
+    // last_report and last_keys measure the current rate.
+    last_report: Instant,
+    last_keys: u64,
+    file: Option<(String, File)>,
+}
+
+impl Tracker {
+    fn new(slots_total: usize, keys_total: u64, now: Instant) -> Tracker {
+        Tracker {
+            start: now,
+            slots_total,
+            slots_done: 0,
+            keys_total,
+            keys_moved: 0,
+            bytes_moved: 0,
+            last_report: now,
+            last_keys: 0,
+            file: None,
+        }
+    }
+
+    // snapshot report the state at now and start a new rate period. The eta
+    // is estimated from the keys moved so far, or the slots done when the
+    // slots are empty.
+    fn snapshot(&mut self, now: Instant) -> Snapshot {
+        let elapsed = now.duration_since(self.start);
+        let period = now.duration_since(self.last_report).as_secs_f64();
+        let keys_per_sec = if period > 0.0 {
+            ((self.keys_moved - self.last_keys) as f64 / period) as u64
+        } else {
+            0
+        };
+        self.last_report = now;
+        self.last_keys = self.keys_moved;
+        let (done, total) = if self.keys_total > 0 {
+            (self.keys_moved, self.keys_total)
+        } else {
+            (self.slots_done as u64, self.slots_total as u64)
+        };
+        let eta_secs = if done == 0 {
+            None
+        } else {
+            let left = total.saturating_sub(done) as f64;
+            Some((elapsed.as_secs_f64() * left / done as f64).round() as u64)
+        };
+        Snapshot {
+            slots_done: self.slots_done,
+            slots_total: self.slots_total,
+            keys_moved: self.keys_moved,
+            keys_total: self.keys_total,
+            bytes_moved: self.bytes_moved,
+            keys_per_sec,
+            elapsed_secs: elapsed.as_secs(),
+            eta_secs,
+        }
+    }
+
+    fn report(&mut self) -> AsResult<()> {
+        let snapshot = self.snapshot(Instant::now());
+        println!("{}", snapshot);
+        if let Some((path, file)) = self.file.as_mut() {
+            let line =
+                serde_json::to_string(&snapshot).map_err(|err| Error::Parse(err.to_string()))?;
+            writeln!(file, "{}", line).map_err(|err| Error::Io(path.clone(), err))?;
+        }
+        Ok(())
+    }
+}
+
+// start track a plan of slots moves holding keys keys in total.
+pub fn start(slots: usize, keys: u64) -> AsResult<()> {
+    let mut tracker = Tracker::new(slots, keys, Instant::now());
+    if let Some(path) = FILE.read().unwrap().clone() {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|err| Error::Io(path.clone(), err))?;
+        tracker.file = Some((path, file));
+    }
+    *TRACKER.lock().unwrap() = Some(tracker);
+    Ok(())
+}
+
+// moved count keys of bytes moved, the progress is reported at most once a second.
+pub fn moved(keys: usize, bytes: u64) -> AsResult<()> {
+    let mut tracker = TRACKER.lock().unwrap();
+    match tracker.as_mut() {
+        Some(tracker) => {
+            tracker.keys_moved += keys as u64;
+            tracker.bytes_moved += bytes;
+            if tracker.last_report.elapsed() < REPORT_INTERVAL {
+                return Ok(());
+            }
+            tracker.report()
+        }
+        None => Ok(()),
+    }
+}
+
+// slot_done count a finished slot move and report the progress.
+pub fn slot_done() -> AsResult<()> {
+    let mut tracker = TRACKER.lock().unwrap();
+    match tracker.as_mut() {
+        Some(tracker) => {
+            tracker.slots_done += 1;
+            tracker.report()
+        }
+        None => Ok(()),
+    }
+}
+
+// finish stop tracking the plan.
+pub fn finish() {
+    *TRACKER.lock().unwrap() = None;
+}
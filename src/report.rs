@@ -0,0 +1,249 @@
+use cluster::{Error, Node};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::str::FromStr;
+#[cfg(test)]
+use topology::Topology;
+
+#[test]
+fn test_parse_columns() {
+    let report = Report::new("addr,role, ops", "csv").unwrap();
+    assert_eq!(
+        report.columns,
+        vec![Column::Addr, Column::Role, Column::Ops]
+    );
+    assert!(Report::new("addr,foo", "csv").is_err());
+    assert!(Report::new("addr", "xml").is_err());
+}
+
+#[test]
+fn test_write_csv() {
+    let report = Report::new("addr,role,used", "csv").unwrap();
+    let mut info = HashMap::new();
+    info.insert("role".to_string(), "master".to_string());
+    info.insert("used_memory".to_string(), "1024".to_string());
+    let rows = vec![report.row("127.0.0.1:7000".to_string(), &info)];
+    let mut out = Vec::new();
+    report.write(&mut out, &rows).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "addr,role,used\n127.0.0.1:7000,master,1024\n"
+    );
+}
+
+#[test]
+fn test_write_json() {
+    let report = Report::new("addr,ops,version", "json").unwrap();
+    let mut info = HashMap::new();
+    info.insert("instantaneous_ops_per_sec".to_string(), "12".to_string());
+    let rows = vec![report.row("127.0.0.1:7000".to_string(), &info)];
+    let mut out = Vec::new();
+    report.write(&mut out, &rows).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        r#"[{"addr":"127.0.0.1:7000","ops":12,"version":""}]"#.to_string() + "\n"
+    );
+}
+
+#[test]
+fn test_unavailable_rows() {
+    let text = "\
+aaaa 127.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-16383
+bbbb 127.0.0.1:7001@17001 master,fail - 0 0 2 disconnected
+cccc 127.0.0.1:7002@17002 handshake - 0 0 0 disconnected
+";
+    let nodes: Vec<Node> = Topology::parse(text)
+        .unwrap()
+        .nodes
+        .iter()
+        .map(|entry| Node::from_entry(entry).unwrap())
+        .collect();
+    let report = Report::new("addr,role", "csv").unwrap();
+    let rows = report
+        .rows(&nodes, |n| {
+            assert_eq!(n.addr(), "127.0.0.1:7000");
+            let mut info = HashMap::new();
+            info.insert("role".to_string(), "master".to_string());
+            Ok(info)
+        })
+        .unwrap();
+    assert_eq!(
+        rows,
+        vec![
+            vec!["127.0.0.1:7000".to_string(), "master".to_string()],
+            vec!["127.0.0.1:7001".to_string(), String::new()],
+            vec!["127.0.0.1:7002".to_string(), String::new()],
+        ]
+    );
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Column {
+    Addr,
+    Pid,
+    Role,
+    Used,
+    Max,
+    Clients,
+    Ops,
+    Cmds,
+    Conns,
+    Version,
+}
+
+impl Column {
+    fn name(self) -> &'static str {
+        match self {
+            Column::Addr => "addr",
+            Column::Pid => "pid",
+            Column::Role => "role",
+            Column::Used => "used",
+            Column::Max => "max",
+            Column::Clients => "clients",
+            Column::Ops => "ops",
+            Column::Cmds => "cmds",
+            Column::Conns => "conns",
+            Column::Version => "version",
+        }
+    }
+
+    // info_key is the INFO field the column is read from.
+    fn info_key(self) -> &'static str {
+        match self {
+            Column::Addr => "",
+            Column::Pid => "process_id",
+            Column::Role => "role",
+            Column::Used => "used_memory",
+            Column::Max => "maxmemory",
+            Column::Clients => "connected_clients",
+            Column::Ops => "instantaneous_ops_per_sec",
+            Column::Cmds => "total_commands_processed",
+            Column::Conns => "total_connections_received",
+            Column::Version => "redis_version",
+        }
+    }
+
+    fn is_number(self) -> bool {
+        !matches!(self, Column::Addr | Column::Role | Column::Version)
+    }
+}
+
+impl FromStr for Column {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Column, Error> {
+        match s.trim() {
+            "addr" => Ok(Column::Addr),
+            "pid" => Ok(Column::Pid),
+            "role" => Ok(Column::Role),
+            "used" => Ok(Column::Used),
+            "max" => Ok(Column::Max),
+            "clients" => Ok(Column::Clients),
+            "ops" => Ok(Column::Ops),
+            "cmds" => Ok(Column::Cmds),
+            "conns" => Ok(Column::Conns),
+            "version" => Ok(Column::Version),
+            other => Err(Error::InvalidArg(format!("unknown column {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Format, Error> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            other => Err(Error::InvalidArg(format!("unknown format {}", other))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    columns: Vec<Column>,
+    format: Format,
+}
+
+impl Report {
+    pub fn new(columns: &str, format: &str) -> Result<Report, Error> {
+        let columns = columns
+            .split(',')
+            .map(Column::from_str)
+            .collect::<Result<Vec<Column>, Error>>()?;
+        Ok(Report {
+            columns,
+            format: format.parse()?,
+        })
+    }
+
+    // collect walk all nodes known by node and return one row per node.
+    pub fn collect(&self, node: &Node) -> Result<Vec<Vec<String>>, Error> {
+        self.rows(&node.nodes()?, |n| n.server_info())
+    }
+
+    // rows query the INFO of the available nodes with info, the unavailable
+    // ones get a row with empty metrics.
+    fn rows<F>(&self, nodes: &[Node], info: F) -> Result<Vec<Vec<String>>, Error>
+    where
+        F: Fn(&Node) -> Result<HashMap<String, String>, Error>,
+    {
+        let mut rows = Vec::new();
+        for n in nodes {
+            if !n.is_available() {
+                rows.push(self.row(n.addr(), &HashMap::new()));
+                continue;
+            }
+            rows.push(self.row(n.addr(), &info(n)?));
+        }
+        Ok(rows)
+    }
+
+    fn row(&self, addr: String, info: &HashMap<String, String>) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|c| match c {
+                Column::Addr => addr.clone(),
+                _ => info.get(c.info_key()).cloned().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W, rows: &[Vec<String>]) -> io::Result<()> {
+        match self.format {
+            Format::Csv => {
+                let header: Vec<&str> = self.columns.iter().map(|c| c.name()).collect();
+                writeln!(w, "{}", header.join(","))?;
+                for row in rows {
+                    writeln!(w, "{}", row.join(","))?;
+                }
+            }
+            Format::Json => {
+                let items: Vec<Value> = rows
+                    .iter()
+                    .map(|row| {
+                        let mut item = Map::new();
+                        for (c, v) in self.columns.iter().zip(row) {
+                            let value = match v.parse::<u64>() {
+                                Ok(num) if c.is_number() => Value::from(num),
+                                _ => Value::from(v.clone()),
+                            };
+                            item.insert(c.name().to_string(), value);
+                        }
+                        Value::Object(item)
+                    })
+                    .collect();
+                writeln!(w, "{}", Value::Array(items))?;
+            }
+        }
+        Ok(())
+    }
+}
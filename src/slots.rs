@@ -0,0 +1,141 @@
+use cluster::{AsResult, Error};
+use std::collections::BTreeSet;
+
+#[test]
+fn test_parse_slots() {
+    assert_eq!(parse("7").unwrap(), vec![7]);
+    assert_eq!(parse(" 3-5, 1,4 ,16383").unwrap(), vec![1, 3, 4, 5, 16383]);
+    assert_eq!(parse("0-16383").unwrap().len(), CLUSTER_SLOTS);
+    assert!(parse("").is_err());
+    assert!(parse("5-3").is_err());
+    assert!(parse("16384").is_err());
+    assert!(parse("1,,2").is_err());
+    assert!(parse("a-b").is_err());
+}
+
+#[test]
+fn test_format_slots() {
+    assert_eq!(ranges(&[1, 2, 3, 5, 7, 8]), vec![(1, 3), (5, 5), (7, 8)]);
+    assert_eq!(
+        format(&[0, 1, 2, 999, 5000, 6000, 6001]),
+        "0-2,999,5000,6000-6001"
+    );
+    assert_eq!(format(&[]), "");
+}
+
+#[test]
+fn test_key_slot() {
+    assert_eq!(crc16(b"123456789"), 0x31c3);
+    assert_eq!(key_slot(b"foo"), 12182);
+    assert_eq!(key_slot(b"bar"), 5061);
+    assert_eq!(
+        key_slot(b"{user1000}.following"),
+        key_slot(b"{user1000}.followers")
+    );
+    assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+    // an empty tag hash the whole key.
+    assert_eq!(
+        key_slot(b"foo{}{bar}"),
+        crc16(b"foo{}{bar}") as usize % CLUSTER_SLOTS
+    );
+    assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+    assert_eq!(
+        key_slot(b"foo{bar"),
+        crc16(b"foo{bar") as usize % CLUSTER_SLOTS
+    );
+    assert_eq!(tag_slot("tenant42"), key_slot(b"{tenant42}.orders"));
+    assert_eq!(tag_slot("{tenant42}"), tag_slot("tenant42"));
+}
+
+const CLUSTER_SLOTS: usize = 16384;
+
+// crc16 is the CRC16-CCITT (XMODEM) used by redis cluster.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// key_slot is the slot of key, only the hash tag is hashed when the key has
+// a non empty one between the first { and the next }.
+pub fn key_slot(key: &[u8]) -> usize {
+    let tag = key.iter().position(|b| *b == b'{').and_then(|open| {
+        let rest = &key[open + 1..];
+        rest.iter()
+            .position(|b| *b == b'}')
+            .filter(|close| *close > 0)
+            .map(|close| &rest[..close])
+    });
+    crc16(tag.unwrap_or(key)) as usize % CLUSTER_SLOTS
+}
+
+// tag_slot is the slot of the keys with hash tag tag, given with or without braces.
+pub fn tag_slot(tag: &str) -> usize {
+    let tag = tag.trim_start_matches('{').trim_end_matches('}');
+    key_slot(format!("{{{}}}", tag).as_bytes())
+}
+
+// parse read a slot set such as "0-999,5000,6000-6100" into sorted slots
+// without duplicates, ranges are inclusive.
+pub fn parse(expr: &str) -> AsResult<Vec<usize>> {
+    let bad = |msg: &str| Error::InvalidArg(format!("bad slot set '{}': {}", expr, msg));
+    let slot = |s: &str| {
+        s.trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|slot| *slot < CLUSTER_SLOTS)
+            .ok_or_else(|| bad(&format!("{} is not a slot", s.trim())))
+    };
+    let mut slots = BTreeSet::new();
+    for item in expr.split(',') {
+        match item.find('-') {
+            Some(idx) => {
+                let (start, end) = (slot(&item[..idx])?, slot(&item[idx + 1..])?);
+                if start > end {
+                    return Err(bad(&format!("empty range {}", item.trim())));
+                }
+                slots.extend(start..=end);
+            }
+            None => {
+                slots.insert(slot(item)?);
+            }
+        }
+    }
+    Ok(slots.into_iter().collect())
+}
+
+// ranges fold sorted slots into inclusive ranges.
+pub fn ranges(slots: &[usize]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for slot in slots {
+        match ranges.last_mut() {
+            Some(last) if last.1 + 1 == *slot => last.1 = *slot,
+            _ => ranges.push((*slot, *slot)),
+        }
+    }
+    ranges
+}
+
+// format write sorted slots back as a slot set.
+pub fn format(slots: &[usize]) -> String {
+    let ranges: Vec<String> = ranges(slots)
+        .into_iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect();
+    ranges.join(",")
+}
@@ -0,0 +1,406 @@
+use cluster::{AsResult, Error};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn test_parse_nodes() {
+    let text = "\
+07c37dfeb235213a872192d90877d0cd55635b91 127.0.0.1:30004@31004 slave e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 0 1426238317239 4 connected
+67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1 127.0.0.1:30002@31002 master - 0 1426238316232 2 connected 5461-10922
+292f8b365bb7edb5e285caf0b7e6ddc7265d2f4f 127.0.0.1:30003@31003 master - 0 1426238318243 3 connected 10923-16383 [10923->-67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1]
+e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 127.0.0.1:30001@31001 myself,master - 0 0 1 connected 0-5460 [5461-<-67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1]
+";
+    let topo = Topology::parse(text).unwrap();
+    assert_eq!(topo.nodes.len(), 4);
+
+    let slave = &topo.nodes[0];
+    assert_eq!(slave.addr, "127.0.0.1:30004");
+    assert_eq!(slave.cport, Some(31004));
+    assert_eq!(
+        slave.master,
+        Some("e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca".to_string())
+    );
+    assert_eq!(slave.pong_recv, 1426238317239);
+    assert_eq!(slave.config_epoch, 4);
+    assert_eq!(slave.link_state, LinkState::Connected);
+    assert!(slave.slots.is_empty());
+
+    let myself = topo.myself().unwrap();
+    assert_eq!(myself.addr, "127.0.0.1:30001");
+    assert_eq!(myself.slots.len(), 5461);
+    assert_eq!(
+        myself.importing.get(&5461).cloned(),
+        Some("67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1".to_string())
+    );
+
+    let migrating = topo
+        .node("292f8b365bb7edb5e285caf0b7e6ddc7265d2f4f")
+        .unwrap();
+    assert_eq!(migrating.slots.len(), 16384 - 10923);
+    assert_eq!(
+        migrating.migrating.get(&10923).cloned(),
+        Some("67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1".to_string())
+    );
+    assert_eq!(topo.masters().count(), 3);
+}
+
+#[test]
+fn test_parse_nodes_conf() {
+    let text = "\
+e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 127.0.0.1:30001@31001 myself,master - 0 0 1 connected 0-16383
+vars currentEpoch 1 lastVoteEpoch 0
+";
+    let topo = Topology::parse(text).unwrap();
+    assert_eq!(topo.nodes.len(), 1);
+    assert_eq!(topo.nodes[0].slots.len(), 16384);
+}
+
+#[test]
+fn test_parse_nodes_err() {
+    assert!(Topology::parse("e7d1eecce10fd6bb 127.0.0.1:30001 master").is_err());
+    assert!(
+        Topology::parse("e7d1eecce10fd6bb 127.0.0.1:30001 master - 0 0 1 connected 0-x").is_err()
+    );
+    assert!(Topology::parse(
+        "e7d1eecce10fd6bb 127.0.0.1:30001 master - 0 0 1 connected 16380-16384"
+    )
+    .is_err());
+}
+
+#[test]
+fn test_parse_addr() {
+    let text = "\
+07c37dfeb235213a872192d90877d0cd55635b91 ::1:30004@31004,redis-4.example.com slave e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 0 0 4 connected
+e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 10.0.0.1:30001@31001, myself,master - 0 0 1 connected 0-16383
+67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1 :0@0 master,noaddr - 0 0 0 connected
+";
+    let topo = Topology::parse(text).unwrap();
+    assert_eq!(topo.nodes[0].addr, "[::1]:30004");
+    assert_eq!(topo.nodes[0].cport, Some(31004));
+    assert_eq!(
+        topo.nodes[0].hostname,
+        Some("redis-4.example.com".to_string())
+    );
+    assert_eq!(topo.nodes[1].addr, "10.0.0.1:30001");
+    assert_eq!(topo.nodes[1].hostname, None);
+    assert_eq!(topo.nodes[2].addr, ":0");
+    assert!(Topology::parse(&text.replace("::1:30004", "10.0.0.4")).is_err());
+
+    assert_eq!(
+        split_addr("[fe80::1]:7000"),
+        Some(("fe80::1".to_string(), "7000".to_string()))
+    );
+    assert_eq!(
+        split_addr("fe80::1:7000"),
+        Some(("fe80::1".to_string(), "7000".to_string()))
+    );
+    assert_eq!(
+        split_addr("redis-1.example.com:7000"),
+        Some(("redis-1.example.com".to_string(), "7000".to_string()))
+    );
+    assert_eq!(split_addr("127.0.0.1"), None);
+    assert_eq!(split_addr("127.0.0.1:"), None);
+    assert_eq!(split_addr("[::1]7000"), None);
+    assert_eq!(split_addr("127.0.0.1:abc"), None);
+    assert_eq!(split_addr("127.0.0.1:65536"), None);
+    assert_eq!(join_addr("::1", "7000"), "[::1]:7000");
+    assert_eq!(join_addr("127.0.0.1", "7000"), "127.0.0.1:7000");
+}
+
+#[test]
+fn test_parse_flags() {
+    let flags = Flags::parse("myself,master");
+    assert!(flags.myself && flags.master && !flags.slave);
+    assert!(flags.is_available());
+
+    let flags = Flags::parse("slave,fail?,nofailover");
+    assert!(flags.slave && flags.pfail && flags.nofailover && !flags.fail);
+    assert!(!flags.is_available());
+    assert_eq!(flags.to_string(), "slave,fail?,nofailover");
+
+    let flags = Flags::parse("master,fail");
+    assert!(flags.fail && !flags.is_available());
+    assert!(!Flags::parse("handshake").is_available());
+    assert!(!Flags::parse("master,noaddr").is_available());
+    assert_eq!(Flags::parse("noflags"), Flags::default());
+}
+
+#[test]
+fn test_parse_link_state() {
+    let text = "\
+07c37dfeb235213a872192d90877d0cd55635b91 127.0.0.1:30004@31004 slave,fail e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 1426238317200 1426238317239 4 disconnected
+";
+    let topo = Topology::parse(text).unwrap();
+    let entry = &topo.nodes[0];
+    assert!(entry.flags.fail);
+    assert_eq!(entry.link_state, LinkState::Disconnected);
+    assert_eq!(entry.ping_sent, 1426238317200);
+    assert!(Topology::parse(&text.replace("disconnected", "linked")).is_err());
+}
+
+const CLUSTER_SLOTS: usize = 16384;
+
+// Flags is the comma separated flags field of CLUSTER NODES.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Flags {
+    pub myself: bool,
+    pub master: bool,
+    pub slave: bool,
+    // fail? in CLUSTER NODES, the node is unreachable from this node's view.
+    pub pfail: bool,
+    pub fail: bool,
+    pub handshake: bool,
+    pub noaddr: bool,
+    pub nofailover: bool,
+}
+
+impl Flags {
+    pub fn parse(s: &str) -> Flags {
+        let mut flags = Flags::default();
+        for flag in s.split(',') {
+            match flag {
+                "myself" => flags.myself = true,
+                "master" => flags.master = true,
+                "slave" => flags.slave = true,
+                "fail?" => flags.pfail = true,
+                "fail" => flags.fail = true,
+                "handshake" => flags.handshake = true,
+                "noaddr" => flags.noaddr = true,
+                "nofailover" => flags.nofailover = true,
+                _ => {}
+            }
+        }
+        flags
+    }
+
+    // is_available report whether commands may be sent to the node.
+    pub fn is_available(&self) -> bool {
+        !(self.pfail || self.fail || self.handshake || self.noaddr)
+    }
+}
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags: Vec<&str> = [
+            (self.myself, "myself"),
+            (self.master, "master"),
+            (self.slave, "slave"),
+            (self.pfail, "fail?"),
+            (self.fail, "fail"),
+            (self.handshake, "handshake"),
+            (self.noaddr, "noaddr"),
+            (self.nofailover, "nofailover"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect();
+        if flags.is_empty() {
+            write!(f, "noflags")
+        } else {
+            write!(f, "{}", flags.join(","))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LinkState {
+    #[default]
+    Connected,
+    Disconnected,
+}
+
+// NodeEntry is one line of CLUSTER NODES output, see
+// https://redis.io/commands/cluster-nodes for the format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeEntry {
+    pub id: String,
+    // addr is host:port, IPv6 hosts are enclosed in brackets.
+    pub addr: String,
+    pub cport: Option<u16>,
+    // hostname is announced by redis 7 with cluster-announce-hostname.
+    pub hostname: Option<String>,
+    pub flags: Flags,
+    pub master: Option<String>,
+    pub ping_sent: u64,
+    pub pong_recv: u64,
+    pub config_epoch: u64,
+    pub link_state: LinkState,
+    pub slots: Vec<usize>,
+    pub migrating: HashMap<usize, String>,
+    pub importing: HashMap<usize, String>,
+}
+
+impl NodeEntry {
+    pub fn is_master(&self) -> bool {
+        self.flags.master
+    }
+
+    pub fn is_myself(&self) -> bool {
+        self.flags.myself
+    }
+}
+
+// Topology is the cluster as seen by a single node. It is built from text
+// only and never touches the network.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topology {
+    pub nodes: Vec<NodeEntry>,
+}
+
+impl Topology {
+    // parse accept the output of CLUSTER NODES or the content of a nodes.conf file.
+    pub fn parse(text: &str) -> AsResult<Topology> {
+        let mut nodes = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            // nodes.conf end with a "vars currentEpoch .." line.
+            if line.is_empty() || line.starts_with("vars ") {
+                continue;
+            }
+            nodes.push(parse_line(line)?);
+        }
+        Ok(Topology { nodes })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> AsResult<Topology> {
+        let text = fs::read_to_string(path.as_ref())
+            .map_err(|err| Error::Io(path.as_ref().display().to_string(), err))?;
+        Topology::parse(&text)
+    }
+
+    pub fn myself(&self) -> Option<&NodeEntry> {
+        self.nodes.iter().find(|n| n.is_myself())
+    }
+
+    pub fn node(&self, id: &str) -> Option<&NodeEntry> {
+        self.nodes.iter().find(|n| n.id == id)
+    }
+
+    pub fn masters(&self) -> impl Iterator<Item = &NodeEntry> {
+        self.nodes.iter().filter(|n| n.is_master())
+    }
+}
+
+fn parse_line(line: &str) -> AsResult<NodeEntry> {
+    let bad = |what: &str| Error::Parse(format!("bad {} in cluster nodes line '{}'", what, line));
+    let kv: Vec<&str> = line.split_whitespace().collect();
+    if kv.len() < 8 {
+        return Err(bad("field count"));
+    }
+    // ip:port@cport,hostname, older versions don't have the cluster bus port
+    // nor the hostname. IPv6 ips are not enclosed in brackets.
+    let mut addr = kv[1].splitn(2, '@');
+    let ip_port = addr.next().unwrap_or_default();
+    let (ip, port) = split_addr(ip_port).ok_or_else(|| bad("address"))?;
+    let mut cport = None;
+    let mut hostname = None;
+    if let Some(bus) = addr.next() {
+        let mut bus = bus.split(',');
+        cport = Some(
+            bus.next()
+                .unwrap_or_default()
+                .parse::<u16>()
+                .map_err(|_| bad("cluster bus port"))?,
+        );
+        hostname = bus
+            .next()
+            .filter(|name| !name.is_empty())
+            .map(|name| name.to_string());
+    }
+    let master = if kv[3] == "-" {
+        None
+    } else {
+        Some(kv[3].to_string())
+    };
+    let num = |s: &str, what: &str| s.parse::<u64>().map_err(|_| bad(what));
+    let link_state = match kv[7] {
+        "connected" => LinkState::Connected,
+        "disconnected" => LinkState::Disconnected,
+        _ => return Err(bad("link state")),
+    };
+
+    let mut slots = vec![];
+    let mut migrating = HashMap::new();
+    let mut importing = HashMap::new();
+    for content in &kv[8..] {
+        if content.starts_with('[') {
+            // trim [ ]
+            let migrate = content.trim_start_matches('[').trim_end_matches(']');
+            let (scope, state): (Vec<&str>, _) = if migrate.contains("->-") {
+                (migrate.split("->-").collect(), &mut migrating)
+            } else if migrate.contains("-<-") {
+                (migrate.split("-<-").collect(), &mut importing)
+            } else {
+                return Err(bad("migrating slot"));
+            };
+            if scope.len() != 2 {
+                return Err(bad("migrating slot"));
+            }
+            state.insert(
+                parse_slot(scope[0]).ok_or_else(|| bad("slot"))?,
+                scope[1].to_string(),
+            );
+        } else {
+            let scope: Vec<&str> = content.split('-').collect();
+            let start = parse_slot(scope[0]).ok_or_else(|| bad("slot"))?;
+            let end = match scope.len() {
+                1 => start,
+                2 => parse_slot(scope[1]).ok_or_else(|| bad("slot"))?,
+                _ => return Err(bad("slot range")),
+            };
+            if end < start {
+                return Err(bad("slot range"));
+            }
+            slots.extend(start..=end);
+        }
+    }
+
+    Ok(NodeEntry {
+        id: kv[0].to_string(),
+        addr: join_addr(&ip, &port),
+        cport,
+        hostname,
+        flags: Flags::parse(kv[2]),
+        master,
+        ping_sent: num(kv[4], "ping sent")?,
+        pong_recv: num(kv[5], "pong recv")?,
+        config_epoch: num(kv[6], "config epoch")?,
+        link_state,
+        slots,
+        migrating,
+        importing,
+    })
+}
+
+// split_addr split host:port into host and port. The host is a name, an IPv4
+// or an IPv6 address with or without brackets, it is empty for a node without
+// address. The port must be a number up to 65535.
+pub fn split_addr(addr: &str) -> Option<(String, String)> {
+    let (host, port) = if addr.starts_with('[') {
+        let end = addr.find("]:")?;
+        (&addr[1..end], &addr[end + 2..])
+    } else {
+        let idx = addr.rfind(':')?;
+        (&addr[..idx], &addr[idx + 1..])
+    };
+    if port.parse::<u16>().is_err() || host.contains(['[', ']']) {
+        return None;
+    }
+    Some((host.to_string(), port.to_string()))
+}
+
+// join_addr is the reverse of split_addr, IPv6 hosts are enclosed in brackets.
+pub fn join_addr(host: &str, port: &str) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+fn parse_slot(s: &str) -> Option<usize> {
+    s.parse::<usize>().ok().filter(|slot| *slot < CLUSTER_SLOTS)
+}
@@ -0,0 +1,129 @@
+use cluster::{AsResult, Error};
+use std::collections::HashMap;
+use std::fs;
+use topology::{join_addr, split_addr};
+
+#[test]
+fn test_parse_inventory() {
+    let zones = Zones::parse(
+        "# rack of every node\n\
+         10.0.0.1:7000 rack1\n\
+         10.0.0.1:7001  rack2 \n\
+         \n\
+         10.0.0.2 rack2\n\
+         [::1] rack3\n\
+         [::2]:7000 rack4\n",
+    )
+    .unwrap();
+    assert_eq!(zones.zone("10.0.0.1", "7000"), Some("rack1"));
+    assert_eq!(zones.zone("10.0.0.1", "7001"), Some("rack2"));
+    assert_eq!(zones.zone("10.0.0.1", "7002"), None);
+    assert_eq!(zones.zone("10.0.0.2", "7005"), Some("rack2"));
+    assert_eq!(zones.zone("::1", "7000"), Some("rack3"));
+    assert_eq!(zones.zone("::2", "7000"), Some("rack4"));
+    assert_eq!(zones.domain("10.0.0.2", "7000"), "zone rack2");
+    assert_eq!(zones.domain("10.0.0.3", "7000"), "host 10.0.0.3");
+    assert!(Zones::parse("10.0.0.1:7000").is_err());
+    assert!(Zones::parse("10.0.0.1:7000 a b").is_err());
+    assert!(Zones::parse("10.0.0.1: a").is_err());
+}
+
+#[test]
+fn test_split_zone() {
+    assert_eq!(
+        split_zone("10.0.0.1:7000@rack1").unwrap(),
+        ("10.0.0.1:7000".to_string(), Some("rack1".to_string()))
+    );
+    assert_eq!(
+        split_zone("[::1]:7000@rack1").unwrap(),
+        ("[::1]:7000".to_string(), Some("rack1".to_string()))
+    );
+    assert_eq!(
+        split_zone("10.0.0.1:7000").unwrap(),
+        ("10.0.0.1:7000".to_string(), None)
+    );
+    assert!(split_zone("10.0.0.1:7000@").is_err());
+
+    // a label given on the command line wins over the inventory.
+    let mut zones = Zones::parse("10.0.0.1 rack1").unwrap();
+    zones.insert("10.0.0.1:7000", "rack9").unwrap();
+    assert_eq!(zones.zone("10.0.0.1", "7000"), Some("rack9"));
+    assert_eq!(zones.zone("10.0.0.1", "7001"), Some("rack1"));
+}
+
+// Zones label nodes with their failure domain, a zone, rack or datacenter.
+// A label is given to a node by host:port or to all the nodes of a host.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Zones {
+    nodes: HashMap<String, String>,
+    hosts: HashMap<String, String>,
+}
+
+impl Zones {
+    pub fn load(path: &str) -> AsResult<Zones> {
+        let text = fs::read_to_string(path).map_err(|err| Error::Io(path.to_string(), err))?;
+        Zones::parse(&text)
+    }
+
+    // parse read "<host:port> <zone>" and "<host> <zone>" lines, IPv6 hosts
+    // are enclosed in brackets. Blank lines and lines start with # are ignored.
+    pub fn parse(text: &str) -> AsResult<Zones> {
+        let mut zones = Zones::default();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let item: Vec<&str> = line.split_whitespace().collect();
+            if item.len() != 2 {
+                return Err(Error::Parse(format!("bad inventory line '{}'", line)));
+            }
+            zones
+                .insert(item[0], item[1])
+                .map_err(|_| Error::Parse(format!("bad inventory line '{}'", line)))?;
+        }
+        Ok(zones)
+    }
+
+    // insert label addr, a host:port or a bare host, with zone.
+    pub fn insert(&mut self, addr: &str, zone: &str) -> AsResult<()> {
+        if addr.starts_with('[') && addr.ends_with(']') {
+            let host = addr[1..addr.len() - 1].to_string();
+            self.hosts.insert(host, zone.to_string());
+        } else if addr.contains(':') {
+            let (host, port) =
+                split_addr(addr).ok_or_else(|| Error::InvalidArg(format!("bad addr {}", addr)))?;
+            self.nodes.insert(join_addr(&host, &port), zone.to_string());
+        } else {
+            self.hosts.insert(addr.to_string(), zone.to_string());
+        }
+        Ok(())
+    }
+
+    pub fn zone(&self, host: &str, port: &str) -> Option<&str> {
+        self.nodes
+            .get(&join_addr(host, port))
+            .or_else(|| self.hosts.get(host))
+            .map(|zone| zone.as_str())
+    }
+
+    // domain is the failure domain of a node, its zone when labelled or else
+    // its host.
+    pub fn domain(&self, host: &str, port: &str) -> String {
+        match self.zone(host, port) {
+            Some(zone) => format!("zone {}", zone),
+            None => format!("host {}", host),
+        }
+    }
+}
+
+// split_zone split the host:port@zone syntax of the command line.
+pub fn split_zone(arg: &str) -> AsResult<(String, Option<String>)> {
+    match arg.find('@') {
+        None => Ok((arg.to_string(), None)),
+        Some(idx) if idx + 1 == arg.len() => {
+            Err(Error::InvalidArg(format!("empty zone in {}", arg)))
+        }
+        Some(idx) => Ok((arg[..idx].to_string(), Some(arg[idx + 1..].to_string()))),
+    }
+}